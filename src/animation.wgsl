@@ -0,0 +1,95 @@
+struct Sprite {
+    top_left_position_x: f32,
+    top_left_position_y: f32,
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    width: f32,
+    height: f32,
+    layer: u32,
+    origin_offset_y: f32,
+
+    frame_num: u32,
+    frame_interval: f32,
+    looping: u32,
+    anim_index: u32,
+    order: u32,
+
+    tint_r: f32,
+    tint_g: f32,
+    tint_b: f32,
+    tint_a: f32,
+    screen_space: u32,
+    flip_x: u32,
+    flip_y: u32,
+    rotation: u32,
+    opacity: f32,
+    z_index: i32,
+    silhouette: u32,
+    sway: f32,
+    palette: u32,
+    outline: u32,
+}
+
+struct Animation {
+    // time at which the current frame started showing
+    counter: f32,
+    current_frame_index: u32,
+    started: u32,
+    _padding: u32,
+}
+
+struct UniformData {
+    height_resolution: f32,
+    texture_width: f32,
+    texture_height: f32,
+    window_width: f32,
+    window_height: f32,
+    utime: f32,
+    delta_time: f32,
+    last_frame_time: f32,
+    sprite_count: u32,
+    camera_x: f32,
+    camera_y: f32,
+    zoom: f32,
+    target_width: f32,
+    target_height: f32,
+    wind: f32,
+}
+
+@group(0) @binding(0) var<uniform> uniform_data: UniformData;
+@group(0) @binding(1) var<storage, read> instance_array: array<Sprite>;
+@group(0) @binding(2) var<storage, read_write> anim_storage_array: array<Animation>;
+
+// one invocation per sprite, so every slot is advanced exactly once a frame no matter
+// how many vertices the sprite has
+@compute @workgroup_size(64)
+fn cs_animate(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= uniform_data.sprite_count {
+        return;
+    }
+    let sprite = instance_array[id.x];
+    if sprite.frame_num <= 1u || sprite.frame_interval <= 0.0 {
+        return;
+    }
+
+    var anim_data = anim_storage_array[sprite.anim_index];
+    if anim_data.started == 0u {
+        anim_data.counter = uniform_data.utime;
+        anim_data.current_frame_index = 0u;
+        anim_data.started = 1u;
+    }
+
+    // catch up on every frame that should have shown since the last dispatch
+    let elapsed = uniform_data.utime - anim_data.counter;
+    if elapsed >= sprite.frame_interval {
+        let steps = u32(floor(elapsed / sprite.frame_interval));
+        anim_data.counter += f32(steps) * sprite.frame_interval;
+        if sprite.looping == 1u {
+            anim_data.current_frame_index = (anim_data.current_frame_index + steps) % sprite.frame_num;
+        } else {
+            // one shot clips hold on their last frame
+            anim_data.current_frame_index = min(anim_data.current_frame_index + steps, sprite.frame_num - 1u);
+        }
+    }
+    anim_storage_array[sprite.anim_index] = anim_data;
+}
@@ -0,0 +1,24 @@
+@group(0) @binding(0) var scene_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// a single triangle twice the size of the viewport, everything outside of it gets clipped
+@vertex
+fn vs_blit(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// nearest neighbour by hand so the scene pixels stay hard edged at any scale
+@fragment
+fn fs_blit(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(scene_texture));
+    let texel = min(vec2<i32>(floor(in.uv * vec2<f32>(size))), size - vec2<i32>(1, 1));
+    return textureLoad(scene_texture, texel, 0);
+}
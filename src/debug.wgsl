@@ -0,0 +1,44 @@
+struct UniformData {
+    height_resolution: f32,
+    texture_width: f32,
+    texture_height: f32,
+    window_width: f32,
+    window_height: f32,
+    utime: f32,
+    delta_time: f32,
+    last_frame_time: f32,
+    sprite_count: u32,
+    camera_x: f32,
+    camera_y: f32,
+    zoom: f32,
+    target_width: f32,
+    target_height: f32,
+    wind: f32,
+}
+
+@group(0) @binding(0) var<uniform> uniform_data: UniformData;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// lines run through the middle of the scene pixel whose top left corner is the point,
+// so a rect lines up with the edge of a sprite at the same position
+fn gridify_centre(pixel_val: f32, camera_val: f32, offset: f32) -> f32 {
+    return ((floor(pixel_val) - floor(camera_val)) * uniform_data.zoom + offset) / uniform_data.height_resolution;
+}
+
+@vertex
+fn vs_debug(@location(0) position: vec2<f32>, @location(1) color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    let scale = uniform_data.target_height / uniform_data.target_width;
+    out.position = vec4<f32>(scale * gridify_centre(position.x, uniform_data.camera_x, 0.5), gridify_centre(position.y, uniform_data.camera_y, -0.5), 0.0, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_debug(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
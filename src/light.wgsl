@@ -0,0 +1,64 @@
+struct UniformData {
+    height_resolution: f32,
+    texture_width: f32,
+    texture_height: f32,
+    window_width: f32,
+    window_height: f32,
+    utime: f32,
+    delta_time: f32,
+    last_frame_time: f32,
+    sprite_count: u32,
+    camera_x: f32,
+    camera_y: f32,
+    zoom: f32,
+    target_width: f32,
+    target_height: f32,
+    wind: f32,
+}
+
+struct Light {
+    position_x: f32,
+    position_y: f32,
+    radius: f32,
+    intensity: f32,
+    color_r: f32,
+    color_g: f32,
+    color_b: f32,
+    _padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> uniform_data: UniformData;
+@group(0) @binding(1) var<storage, read> light_array: array<Light>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // -1 to 1 across the light's square, the centre is 0
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec3<f32>,
+}
+
+fn gridify(pixel_val: f32, camera_val: f32) -> f32 {
+    return (floor(pixel_val) - floor(camera_val)) * uniform_data.zoom / uniform_data.height_resolution;
+}
+
+// every light is a square around its position just big enough to hold the radius
+@vertex
+fn vs_light(@location(0) corner: vec2<f32>, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let light = light_array[instance_index];
+    let scale = uniform_data.target_height / uniform_data.target_width;
+    let local = vec2<f32>(corner.x * 2.0 - 1.0, 1.0 - corner.y * 2.0);
+    let position_x = light.position_x + local.x * light.radius;
+    let position_y = light.position_y + local.y * light.radius;
+    out.position = vec4<f32>(scale * gridify(position_x, uniform_data.camera_x), gridify(position_y, uniform_data.camera_y), 0.0, 1.0);
+    out.local = local;
+    out.color = vec3<f32>(light.color_r, light.color_g, light.color_b) * light.intensity;
+    return out;
+}
+
+// added on top of the ambient colour the light map was cleared to
+@fragment
+fn fs_light(in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = clamp(1.0 - length(in.local), 0.0, 1.0);
+    return vec4<f32>(in.color * falloff * falloff, 1.0);
+}
@@ -8,8 +8,10 @@
 )]
 #![feature(path_file_prefix, alloc_layout_extra)]
 
+use bytemuck::Zeroable;
 use image::EncodableLayout;
 use std::mem::size_of;
+use wgpu::util::DeviceExt;
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
@@ -22,6 +24,14 @@ struct UniformData {
     utime: f32,
     delta_time: f32,
     last_frame_time: f32,
+    sprite_count: u32,
+    camera_x: f32,
+    camera_y: f32,
+    zoom: f32,
+    target_width: f32,
+    target_height: f32,
+    // -1 to 1 from Weather, positive blows to the right
+    wind: f32,
 }
 
 #[repr(C)]
@@ -33,12 +43,46 @@ struct Sprite {
     top_left_tex_coords_y: f32,
     width: f32,
     height: f32,
-    depth_base: f32,
+    // id from RenderLayers
+    layer: u32,
     origin_offset_y: f32,
 
     frame_num: u32,
     frame_interval: f32,
     looping: u32,
+    // slot in the anim storage buffer, assigned by the renderer and kept for as long as the
+    // sprite's entity is loaded
+    anim_index: u32,
+    // position in the last load, what SortMode::Insertion and depth ties go by. assigned by
+    // the renderer before sorting
+    order: u32,
+
+    // multiplied with the atlas colour
+    tint_r: f32,
+    tint_g: f32,
+    tint_b: f32,
+    tint_a: f32,
+    // 1 to ignore the camera, the position is then relative to the centre of the screen
+    screen_space: u32,
+    // mirrored on screen when 1, applied before the rotation
+    flip_x: u32,
+    flip_y: u32,
+    // quarter turns clockwise, the sprite stays anchored at its top left corner
+    rotation: u32,
+    // multiplied into tint_a, kept apart so fading doesn't lose the tint
+    opacity: f32,
+    // higher is further in front, only used on SortMode::ZIndex layers
+    z_index: i32,
+    // packed rgba8 drawn over the parts of the sprite that something else covers, 0 for none.
+    // see pack_color
+    silhouette: u32,
+    // how many pixels the top leans over at full wind, for crops, grass and trees
+    sway: f32,
+    // row in the palette texture the atlas colours are swapped through, 0 keeps them
+    palette: u32,
+    // packed rgba8 drawn one pixel wide around the opaque texels, 0 for none. the quad grows
+    // by a pixel on every side to make room for it. see pack_color
+    outline: u32,
 }
 impl Sprite {
     fn empty() -> Self {
@@ -49,20 +93,135 @@ impl Sprite {
             top_left_tex_coords_y: 0.0,
             width: 0.0,
             height: 0.0,
-            depth_base: 0.0,
+            layer: 0,
             origin_offset_y: 0.0,
             frame_num: 0,
             frame_interval: 0.0,
             looping: 0,
+            anim_index: 0,
+            order: 0,
+            tint_r: 1.0,
+            tint_g: 1.0,
+            tint_b: 1.0,
+            tint_a: 1.0,
+            screen_space: 0,
+            flip_x: 0,
+            flip_y: 0,
+            rotation: 0,
+            opacity: 1.0,
+            z_index: 0,
+            silhouette: 0,
+            sway: 0.0,
+            palette: 0,
+            outline: 0,
+        }
+    }
+
+    /// on screen, quarter turns swap the sides
+    fn size(&self) -> (f32, f32) {
+        match self.rotation % 2 {
+            0 => (self.width, self.height),
+            _ => (self.height, self.width),
         }
     }
 }
 
+/// rgba from 0 to 1 into the rgba8 layout unpack4x8unorm reads, red in the lowest byte
+fn pack_color(color: [f32; 4]) -> u32 {
+    color.iter().rev().fold(0, |packed, channel| {
+        packed << 8 | (channel.clamp(0.0, 1.0) * 255.0).round() as u32
+    })
+}
+
+/// per sprite animation state, only ever written by the animation compute shader
 #[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct Animation {
+    // time at which the current frame started showing
+    counter: f32,
+    current_frame_index: u32,
+    started: u32,
+    _padding: u32,
+}
+
+#[derive(Debug)]
 struct TextureData {
     top_left_tex_pos: (u32, u32),
     size: (u32, u32),
     origin_offset: u32,
+    frame_num: u32,
+    frame_interval: f32,
+    looping: bool,
+    // already looked up in RenderLayers
+    layer: u32,
+    tint: [f32; 4],
+    opacity: f32,
+    flip_x: bool,
+    flip_y: bool,
+    rotation: u32,
+    silhouette: Option<[f32; 4]>,
+    sway: f32,
+    // already looked up in Palettes
+    palette: u32,
+    material: TileMaterial,
+}
+
+/// an entry in prefabs.json, the art is the packed sprite of the same name unless `sprite`
+/// names another one
+#[derive(serde::Deserialize, Debug)]
+struct PrefabDefinition {
+    // for prefabs sharing their art, like the same character in another palette
+    sprite: Option<String>,
+    // of one frame, a packed strip is split into frame_num frames when this is left out
+    size: Option<(u32, u32)>,
+    origin_offset: Option<u32>,
+    #[serde(default = "PrefabDefinition::default_frame_num")]
+    frame_num: u32,
+    #[serde(default)]
+    frame_interval: f32,
+    #[serde(default)]
+    looping: bool,
+    #[serde(default = "PrefabDefinition::default_layer")]
+    layer: String,
+    #[serde(default = "PrefabDefinition::default_tint")]
+    tint: [f32; 4],
+    #[serde(default = "PrefabDefinition::default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    flip_x: bool,
+    #[serde(default)]
+    flip_y: bool,
+    #[serde(default)]
+    rotation: u32,
+    // shown where the sprite is covered, for the player and npcs
+    #[serde(default)]
+    silhouette: Option<[f32; 4]>,
+    // leans with the wind, see Sprite::sway
+    #[serde(default)]
+    sway: f32,
+    // a name from palettes.json, the same sprite in other colours
+    #[serde(default)]
+    palette: Option<String>,
+    // only used when the prefab is registered as a tile
+    #[serde(default)]
+    material: TileMaterial,
+}
+impl PrefabDefinition {
+    fn default_frame_num() -> u32 {
+        1
+    }
+
+    fn default_tint() -> [f32; 4] {
+        [1.0, 1.0, 1.0, 1.0]
+    }
+
+    fn default_opacity() -> f32 {
+        1.0
+    }
+
+    fn default_layer() -> String {
+        "objects".to_string()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -78,40 +237,289 @@ enum CloseStatus {
     Closed,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScaleMode {
+    // whole multiples of the scene resolution only, the rest is filled with bars
+    Integer,
+    // as large as fits while keeping the aspect ratio, pixels may end up uneven
+    Fit,
+    // fills the window and ignores the aspect ratio
+    Stretch,
+}
+impl ScaleMode {
+    fn next(self) -> Self {
+        match self {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Stretch,
+            ScaleMode::Stretch => ScaleMode::Integer,
+        }
+    }
+}
+
+/// where the low resolution scene lands in the window, in window pixels
+#[derive(Clone, Copy, Debug)]
+struct Viewport {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    target_width: f32,
+    target_height: f32,
+}
+impl Viewport {
+    fn new(scale_mode: ScaleMode, target_size: (u32, u32), window_size: (u32, u32)) -> Self {
+        let (target_width, target_height) = (target_size.0 as f32, target_size.1 as f32);
+        let (window_width, window_height) = (window_size.0 as f32, window_size.1 as f32);
+        let fit_scale = (window_width / target_width).min(window_height / target_height);
+        let (width, height) = match scale_mode {
+            ScaleMode::Integer => {
+                // a window smaller than the scene can't fit a single whole multiple, so it shrinks like fit
+                let scale = if fit_scale >= 1.0 {
+                    fit_scale.floor()
+                } else {
+                    fit_scale
+                };
+                (target_width * scale, target_height * scale)
+            }
+            ScaleMode::Fit => (target_width * fit_scale, target_height * fit_scale),
+            ScaleMode::Stretch => (window_width, window_height),
+        };
+        Self {
+            x: ((window_width - width) / 2.0).floor(),
+            y: ((window_height - height) / 2.0).floor(),
+            width,
+            height,
+            target_width,
+            target_height,
+        }
+    }
+}
+
 struct PixelRenderer {
-    surface: wgpu::Surface,
+    // none when running headless, frames then go to offscreen_target instead
+    surface: Option<wgpu::Surface>,
     surface_config: wgpu::SurfaceConfiguration,
+    offscreen_target: Option<wgpu::Texture>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     shader: wgpu::ShaderModule,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    // opaque texels write depth, everything else goes through translucent_pipeline afterwards
     pipeline: wgpu::RenderPipeline,
+    translucent_pipeline: wgpu::RenderPipeline,
+    // covered parts of sprites with a silhouette colour, after everything else
+    silhouette_pipeline: wgpu::RenderPipeline,
+
+    anim_shader: wgpu::ShaderModule,
+    anim_bind_group_layout: wgpu::BindGroupLayout,
+    anim_bind_group: wgpu::BindGroup,
+    anim_pipeline: wgpu::ComputePipeline,
 
     texture_atlas_array: wgpu::Texture,
     depth_stencil_texture: wgpu::Texture,
+    depth_stencil_format: wgpu::TextureFormat,
+
+    // the world is drawn here at height_resolution and then scaled up onto the surface
+    scene_texture: wgpu::Texture,
+    blit_shader: wgpu::ShaderModule,
+    blit_bind_group_layout: wgpu::BindGroupLayout,
+    // one reading scene_texture and one reading post_texture, whichever the chain ended on
+    blit_bind_groups: [wgpu::BindGroup; 2],
+    blit_pipeline: wgpu::RenderPipeline,
+    scale_mode: ScaleMode,
+    viewport: Viewport,
 
     uniform_data: UniformData,
     start_time: std::time::Instant,
 
     uniform_buffer: wgpu::Buffer,
-    storage_buffer: wgpu::Buffer,
+    // one unit quad shared by every sprite, the sprites themselves are instances
+    quad_vertex_buffer: wgpu::Buffer,
+    quad_index_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    // instance indices back to front across all three runs, rewritten every frame by draw
+    draw_order_buffer: wgpu::Buffer,
     anim_storage_buffer: wgpu::Buffer,
+    // the dynamic sprites' anim slots by key, counted from the end of the static ones.
+    // slots given up go on the free list and are handed out again before new ones
+    anim_slots: std::collections::HashMap<u64, u32>,
+    free_anim_slots: Vec<u32>,
+    next_anim_slot: u32,
 
+    // static sprites sit at the front of the instance buffer and are only written when
+    // load_static_sprites is called, dynamic ones follow right after them
+    static_sprites: Vec<Sprite>,
+    // last snapshot handed to load_sprites, in query order, used to find what changed
+    raw_keys: Vec<u64>,
+    raw_sprites: Vec<Sprite>,
     sorted_sprites: Vec<Sprite>,
+    // position in sorted_sprites for every entry of raw_sprites
+    sorted_positions: Vec<usize>,
+    // glyphs from BitmapFont::layout, uploaded after the dynamic sprites as their own run.
+    // the raw copy is what load_text_sprites was last given, before sorting
+    raw_text_sprites: Vec<Sprite>,
+    text_sprites: Vec<Sprite>,
+
+    // what sprites and tiles are sorted by, the gpu gets each layer's depth band and sort mode
+    layers: RenderLayers,
+    layer_buffer: wgpu::Buffer,
+
+    // names for the rows of palette_texture, prefabs pick theirs by name
+    palettes: Palettes,
+    palette_texture: wgpu::Texture,
+
+    // ground tiles skip the sort entirely, each chunk is one draw call
+    tile_bind_group_layout: wgpu::BindGroupLayout,
+    tile_pipeline: wgpu::RenderPipeline,
+    tile_translucent_pipeline: wgpu::RenderPipeline,
+    // skipped while no chunk has water in it
+    water_mask_pipeline: wgpu::RenderPipeline,
+    reflection_pipeline: wgpu::RenderPipeline,
+    tile_chunks: std::collections::HashMap<(u32, i32, i32), ChunkBuffers>,
+
+    // a fixed ring of particles stepped by particle.wgsl, new ones overwrite the oldest
+    particle_buffer: wgpu::Buffer,
+    particle_step_buffer: wgpu::Buffer,
+    particle_compute_bind_group: wgpu::BindGroup,
+    particle_compute_pipeline: wgpu::ComputePipeline,
+    particle_bind_group_layout: wgpu::BindGroupLayout,
+    particle_bind_group: wgpu::BindGroup,
+    // every slot once for all layers and then once per layer, see draw_particles
+    particle_index_buffer: wgpu::Buffer,
+    particle_pipeline: wgpu::RenderPipeline,
+    particle_translucent_pipeline: wgpu::RenderPipeline,
+    next_particle: usize,
+    // utime the particles were last stepped to, nothing is stepped or drawn once it passes
+    // particles_alive_until
+    particle_time: f32,
+    particles_alive_until: f32,
+
+    // ambient colour plus point lights at scene resolution, multiplied into every lit
+    // sprite and tile by fs_main
+    light_texture: wgpu::Texture,
+    light_shader: wgpu::ShaderModule,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group: wgpu::BindGroup,
+    light_pipeline: wgpu::RenderPipeline,
+    light_buffer: wgpu::Buffer,
+    light_count: u32,
+    ambient: [f32; 3],
+
+    // post effects ping-pong between scene_texture and post_texture before the blit
+    post_texture: wgpu::Texture,
+    post_shader: wgpu::ShaderModule,
+    post_bind_group_layout: wgpu::BindGroupLayout,
+    // reading scene_texture and post_texture, same order as blit_bind_groups
+    post_bind_groups: [wgpu::BindGroup; 2],
+    // indexed by PostEffectKind
+    post_pipelines: Vec<wgpu::RenderPipeline>,
+    post_buffer: wgpu::Buffer,
+    lut_texture: wgpu::Texture,
+    // enabled effects in the order they run
+    post_chain: Vec<PostEffectKind>,
+    // how far the wind has carried the fog since the start, world units
+    fog_drift: f32,
+    // picked from the adapter, how much weather it can take
+    weather_quality: WeatherQuality,
+
+    // lines from DebugDraw, drawn over the finished scene and skipped when there are none
+    debug_bind_group: wgpu::BindGroup,
+    debug_pipeline: wgpu::RenderPipeline,
+    debug_buffer: wgpu::Buffer,
+    debug_vertex_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SpritePass {
+    // alpha tested, writes depth
+    Opaque,
+    // only the half transparent texels the opaque pass threw away, blended without writing depth
+    Translucent,
+    // only where something nearer already covers the sprite, see fs_silhouette
+    Silhouette,
+    // stencils the visible water right after the opaque tiles, no colour
+    WaterMask,
+    // mirrored sprites, only inside the water stencil
+    Reflection,
+}
+
+/// the stencil bit fs_water_mask sets, silhouettes count in the bits below it
+const WATER_STENCIL: u32 = 0x80;
+
+/// the gpu side of one tilemap chunk
+struct ChunkBuffers {
+    chunk_buffer: wgpu::Buffer,
+    tile_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // world position of the bottom left corner and the length of a side, for culling
+    origin: (f32, f32),
+    extent: f32,
+    water: bool,
+}
+
+/// everything bind group 0 of the sprite and tile pipelines points at, by binding
+struct SpriteBindings<'a> {
+    texture_atlas_array: &'a wgpu::Texture,
+    uniform_buffer: &'a wgpu::Buffer,
+    instance_buffer: &'a wgpu::Buffer,
+    anim_storage_buffer: &'a wgpu::Buffer,
+    light_texture: &'a wgpu::Texture,
+    layer_buffer: &'a wgpu::Buffer,
+    palette_texture: &'a wgpu::Texture,
+}
+
+#[derive(Debug)]
+enum RendererError {
+    CreateSurface(wgpu::CreateSurfaceError),
+    NoAdapter,
+    UnsupportedSurface,
+    RequestDevice(wgpu::RequestDeviceError),
+    Surface(wgpu::SurfaceError),
+    InvalidLut { width: u32, height: u32 },
+    InvalidPalette { height: u32, rows: u32 },
+    ShaderCompile(String),
+    NoLayers,
+}
+impl std::fmt::Display for RendererError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RendererError::CreateSurface(err) => write!(f, "could not create a surface: {err}"),
+            RendererError::NoAdapter => write!(f, "no graphics adapter, not even a software one"),
+            RendererError::UnsupportedSurface => {
+                write!(f, "the adapter can't present to this window")
+            }
+            RendererError::RequestDevice(err) => write!(f, "could not open the device: {err}"),
+            RendererError::Surface(err) => write!(f, "lost the surface: {err}"),
+            RendererError::InvalidLut { width, height } => write!(
+                f,
+                "colour grading lut is {width}x{height}, it should be n slices of n by n side by side"
+            ),
+            RendererError::InvalidPalette { height, rows } => write!(
+                f,
+                "palette image is {height} rows tall, it needs the source row plus one per named palette ({rows})"
+            ),
+            RendererError::NoLayers => write!(f, "at least one render layer is needed"),
+            RendererError::ShaderCompile(err) => {
+                write!(f, "shader failed to compile, keeping the old one: {err}")
+            }
+        }
+    }
 }
+impl std::error::Error for RendererError {}
 
 impl PixelRenderer {
-    fn new(window: &winit::window::Window, height_resolution: u32) -> Self {
+    fn new(window: &winit::window::Window, height_resolution: u32) -> Result<Self, RendererError> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
-        let surface = unsafe { instance.create_surface(window).unwrap() };
+        let surface =
+            unsafe { instance.create_surface(window) }.map_err(RendererError::CreateSurface)?;
         let adapter =
             pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 force_fallback_adapter: false,
                 compatible_surface: Some(&surface),
             }))
-            .unwrap();
+            .ok_or(RendererError::NoAdapter)?;
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
@@ -120,8 +528,17 @@ impl PixelRenderer {
             },
             None,
         ))
-        .unwrap();
-        let surface_texture_format = surface.get_capabilities(&adapter).formats[0];
+        .map_err(RendererError::RequestDevice)?;
+        // both lists are empty when the adapter can't present to this surface at all
+        let capabilities = surface.get_capabilities(&adapter);
+        let surface_texture_format = *capabilities
+            .formats
+            .first()
+            .ok_or(RendererError::UnsupportedSurface)?;
+        let alpha_mode = *capabilities
+            .alpha_modes
+            .first()
+            .ok_or(RendererError::UnsupportedSurface)?;
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -130,78 +547,171 @@ impl PixelRenderer {
             height: window.inner_size().height,
             // this mode is always available, you can also get the first one in the present mode vec from capabilities
             present_mode: wgpu::PresentMode::Immediate,
-            alpha_mode: surface.get_capabilities(&adapter).alpha_modes[0],
+            alpha_mode,
             view_formats: vec![],
         };
 
         surface.configure(&device, &surface_config);
 
+        Self::from_device(
+            device,
+            queue,
+            Some(surface),
+            surface_config,
+            height_resolution,
+            WeatherQuality::for_adapter(&adapter.get_info()),
+        )
+    }
+
+    /// renders into an offscreen texture instead of a window, for ci and golden image tests,
+    /// falls back to a software adapter (lavapipe, llvmpipe, warp) when there is no gpu
+    fn new_headless(
+        width: u32,
+        height: u32,
+        height_resolution: u32,
+    ) -> Result<Self, RendererError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            }))
+            .or_else(|| {
+                pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptionsBase {
+                    power_preference: wgpu::PowerPreference::LowPower,
+                    force_fallback_adapter: true,
+                    compatible_surface: None,
+                }))
+            })
+            .ok_or(RendererError::NoAdapter)?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: adapter.features(),
+                limits: adapter.limits(),
+            },
+            None,
+        ))
+        .map_err(RendererError::RequestDevice)?;
+
+        // never handed to a surface, only used to keep the size and format of the offscreen target
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Immediate,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        Self::from_device(
+            device,
+            queue,
+            None,
+            surface_config,
+            height_resolution,
+            WeatherQuality::for_adapter(&adapter.get_info()),
+        )
+    }
+
+    fn from_device(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface>,
+        surface_config: wgpu::SurfaceConfiguration,
+        height_resolution: u32,
+        weather_quality: WeatherQuality,
+    ) -> Result<Self, RendererError> {
+        let surface_texture_format = surface_config.format;
+        // software adapters usually don't support the 32 bit float variant
+        let depth_stencil_format = if device
+            .features()
+            .contains(wgpu::Features::DEPTH32FLOAT_STENCIL8)
+        {
+            wgpu::TextureFormat::Depth32FloatStencil8
+        } else {
+            wgpu::TextureFormat::Depth24PlusStencil8
+        };
+        let offscreen_target = match surface {
+            Some(_) => None,
+            None => Some(Self::create_offscreen_target(&device, &surface_config)),
+        };
+
         // TEXTURE LOADING
-        let mut dir = std::env::current_dir().unwrap();
-        dir.push("src/res/texture_pack.png");
-        let texture_data = image::io::Reader::open(dir)
-            .unwrap()
-            .decode()
-            .unwrap()
-            .into_rgba8();
+        // nothing until load_atlas is given the packed one
+        let texture_data = image::RgbaImage::new(1, 1);
+        let texture_atlas_array = Self::create_atlas_texture(&device, &queue, &texture_data);
 
-        let texture_atlas_array = device.create_texture(&wgpu::TextureDescriptor {
+        // SCENE TARGET
+        // fixed at height_resolution, the width follows the window's aspect ratio
+        let target_height = height_resolution;
+        let target_width =
+            Self::target_width(target_height, (surface_config.width, surface_config.height));
+        let scene_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size: wgpu::Extent3d {
-                width: texture_data.width(),
-                height: texture_data.height(),
+                width: target_width,
+                height: target_height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
-        queue.write_texture(
-            wgpu::ImageCopyTextureBase {
-                texture: &texture_atlas_array,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            texture_data.as_bytes(),
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * texture_data.width()),
-                rows_per_image: Some(texture_data.height()),
-            },
-            texture_atlas_array.size(),
+        let scale_mode = ScaleMode::Integer;
+        let viewport = Viewport::new(
+            scale_mode,
+            (target_width, target_height),
+            (surface_config.width, surface_config.height),
         );
 
         // depth texture
         let depth_stencil_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size: wgpu::Extent3d {
-                width: surface_config.width,
-                height: surface_config.height,
+                width: target_width,
+                height: target_height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32FloatStencil8,
+            format: depth_stencil_format,
             usage: wgpu::TextureUsages::COPY_DST
                 | wgpu::TextureUsages::COPY_SRC
                 | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
 
+        // half floats so lights can add up past white before they're clamped
+        let light_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: target_width,
+                height: target_height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
             size: size_of::<UniformData>() as u64,
             usage: wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::UNIFORM
-                | wgpu::BufferUsages::MAP_READ,
+                | wgpu::BufferUsages::UNIFORM,
             mapped_at_creation: false,
         });
 
@@ -211,32 +721,51 @@ impl PixelRenderer {
             height_resolution: (height_resolution / 2) as f32,
             texture_width: texture_data.width() as f32,
             texture_height: texture_data.height() as f32,
-            window_width: window.inner_size().width as f32,
-            window_height: window.inner_size().height as f32,
+            window_width: surface_config.width as f32,
+            window_height: surface_config.height as f32,
             utime: 0.0,
             delta_time: 0.0,
             last_frame_time: 0.0,
+            sprite_count: 0,
+            camera_x: 0.0,
+            camera_y: 0.0,
+            zoom: 1.0,
+            target_width: target_width as f32,
+            target_height: target_height as f32,
+            wind: 0.0,
         };
         queue.write_buffer(&uniform_buffer, 0, bytemuck::cast_slice(&[uniform_data]));
 
-        let size_of_storage = 16;
-        let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        // corners of the quad as fractions of the sprite size, y pointing down like the atlas
+        let quad_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[[0.0f32, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let quad_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[0u16, 1, 2, 1, 3, 2]),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let instance_capacity = 16;
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: size_of::<Sprite>() as u64 * size_of_storage,
+            size: size_of::<Sprite>() as u64 * instance_capacity,
             usage: wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::MAP_READ,
+                | wgpu::BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
 
+        let draw_order_buffer = Self::create_draw_order_buffer(&device, instance_capacity);
+
         let anim_storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: 8 * size_of_storage,
+            size: size_of::<Animation>() as u64 * instance_capacity,
             usage: wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::COPY_SRC
-                | wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::MAP_READ,
+                | wgpu::BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
 
@@ -266,12 +795,13 @@ impl PixelRenderer {
                     },
                     count: None,
                 },
-                // vertex data
+                // vertex data, read only since writable storage in the vertex stage
+                // is an optional feature that software adapters don't have
                 wgpu::BindGroupLayoutEntry {
                     binding: 2,
                     visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -281,96 +811,358 @@ impl PixelRenderer {
                     binding: 3,
                     visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // light map
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // render layers
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                     count: None,
                 },
+                // palettes, the source colours in the first row and a swap in every row below
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let layers = RenderLayers::new();
+        let layer_buffer = Self::create_layer_buffer(&device, &layers);
+        // a single row only holds source colours, so nothing is swapped until load_palettes
+        let palettes = Palettes::new();
+        let palette_texture =
+            Self::create_atlas_texture(&device, &queue, &image::RgbaImage::new(1, 1));
+        let bind_group = Self::create_bind_group(
+            &device,
+            &bind_group_layout,
+            &SpriteBindings {
+                texture_atlas_array: &texture_atlas_array,
+                uniform_buffer: &uniform_buffer,
+                instance_buffer: &instance_buffer,
+                anim_storage_buffer: &anim_storage_buffer,
+                light_texture: &light_texture,
+                layer_buffer: &layer_buffer,
+                palette_texture: &palette_texture,
+            },
+        );
+
+        // SHADER
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./shader.wgsl"));
+
+        // PIPELINE
+        let pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_main",
+            SpritePass::Opaque,
+            &[&bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let translucent_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_main",
+            SpritePass::Translucent,
+            &[&bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let silhouette_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_silhouette",
+            SpritePass::Silhouette,
+            &[&bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+
+        // TILEMAP
+        let tile_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // where the chunk sits and at what depth
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // one entry per cell
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let tile_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_tile",
+            SpritePass::Opaque,
+            &[&bind_group_layout, &tile_bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let tile_translucent_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_tile",
+            SpritePass::Translucent,
+            &[&bind_group_layout, &tile_bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let water_mask_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_tile",
+            SpritePass::WaterMask,
+            &[&bind_group_layout, &tile_bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let reflection_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_reflection",
+            SpritePass::Reflection,
+            &[&bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+
+        // PARTICLES
+        let particle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (size_of::<Particle>() * PARTICLE_CAPACITY) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let particle_step_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<ParticleStep>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        let particle_compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let particle_compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout: &bind_group_layout,
+            layout: &particle_compute_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_atlas_array.create_view(
-                        &wgpu::TextureViewDescriptor {
-                            label: None,
-                            format: None,
-                            dimension: None,
-                            aspect: wgpu::TextureAspect::All,
-                            base_mip_level: 0,
-                            mip_level_count: None,
-                            base_array_layer: 0,
-                            array_layer_count: None,
-                        },
-                    )),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
                     resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &uniform_buffer,
+                        buffer: &particle_step_buffer,
                         offset: 0,
                         size: None,
                     }),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &storage_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
+                    binding: 1,
                     resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &anim_storage_buffer,
+                        buffer: &particle_buffer,
                         offset: 0,
                         size: None,
                     }),
                 },
             ],
         });
-
-        // SHADER
-        let shader = device.create_shader_module(wgpu::include_wgsl!("./shader.wgsl"));
-
-        // PIPELINE
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let particle_shader = device.create_shader_module(wgpu::include_wgsl!("./particle.wgsl"));
+        let particle_compute_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(
+                    &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: None,
+                        bind_group_layouts: &[&particle_compute_bind_group_layout],
+                        push_constant_ranges: &[],
+                    }),
+                ),
+                module: &particle_shader,
+                entry_point: "cs_particles",
+            });
+        // the same buffer read only, for vs_particle
+        let particle_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let particle_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout: Some(
-                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: None,
-                    bind_group_layouts: &[&bind_group_layout],
-                    push_constant_ranges: &[],
+            layout: &particle_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &particle_buffer,
+                    offset: 0,
+                    size: None,
                 }),
-            ),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[],
-            },
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32FloatStencil8,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_texture_format,
+            }],
+        });
+        let particle_index_buffer = Self::create_particle_index_buffer(&device, &layers);
+        let particle_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_particle",
+            SpritePass::Opaque,
+            &[&bind_group_layout, &particle_bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+        let particle_translucent_pipeline = Self::create_sprite_pipeline(
+            &device,
+            &shader,
+            "vs_particle",
+            SpritePass::Translucent,
+            &[&bind_group_layout, &particle_bind_group_layout],
+            depth_stencil_format,
+            scene_texture.format(),
+        );
+
+        // LIGHTING
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<LightInstance>() as u64 * 16,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let light_bind_group = Self::create_light_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &uniform_buffer,
+            &light_buffer,
+        );
+        let light_shader = device.create_shader_module(wgpu::include_wgsl!("./light.wgsl"));
+        let light_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&light_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &light_shader,
+                entry_point: "vs_light",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: size_of::<[f32; 2]>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+                }],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &light_shader,
+                entry_point: "fs_light",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: light_texture.format(),
+                    // lights add up
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
                             operation: wgpu::BlendOperation::Add,
                         },
                         alpha: wgpu::BlendComponent {
@@ -385,280 +1177,3724 @@ impl PixelRenderer {
             multiview: None,
         });
 
-        Self {
+        // ANIMATION
+        let anim_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let anim_bind_group = Self::create_anim_bind_group(
+            &device,
+            &anim_bind_group_layout,
+            &uniform_buffer,
+            &instance_buffer,
+            &anim_storage_buffer,
+        );
+        let anim_shader = device.create_shader_module(wgpu::include_wgsl!("./animation.wgsl"));
+        let anim_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&anim_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &anim_shader,
+            entry_point: "cs_animate",
+        });
+
+        // BLIT
+        let blit_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            });
+        let post_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: scene_texture.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: scene_texture.format(),
+            usage: scene_texture.usage(),
+            view_formats: &[],
+        });
+        let blit_bind_groups = [
+            Self::create_blit_bind_group(&device, &blit_bind_group_layout, &scene_texture),
+            Self::create_blit_bind_group(&device, &blit_bind_group_layout, &post_texture),
+        ];
+        let blit_shader = device.create_shader_module(wgpu::include_wgsl!("./blit.wgsl"));
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&blit_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &blit_shader,
+                entry_point: "vs_blit",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &blit_shader,
+                entry_point: "fs_blit",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_texture_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        // POST EFFECTS
+        let post_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<PostData>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        // the same format the atlas uses, so graded colours come out linear
+        let lut_texture = Self::create_atlas_texture(&device, &queue, &identity_lut(16));
+        let post_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let post_bind_groups = [
+            Self::create_post_bind_group(
+                &device,
+                &post_bind_group_layout,
+                &scene_texture,
+                &post_buffer,
+                &lut_texture,
+            ),
+            Self::create_post_bind_group(
+                &device,
+                &post_bind_group_layout,
+                &post_texture,
+                &post_buffer,
+                &lut_texture,
+            ),
+        ];
+        let post_shader = device.create_shader_module(wgpu::include_wgsl!("./post.wgsl"));
+        let post_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&post_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let post_pipelines = PostEffectKind::ALL
+            .iter()
+            .map(|kind| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: None,
+                    layout: Some(&post_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &post_shader,
+                        entry_point: "vs_post",
+                        buffers: &[],
+                    },
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &post_shader,
+                        entry_point: kind.entry_point(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: scene_texture.format(),
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview: None,
+                })
+            })
+            .collect();
+
+        // DEBUG DRAW
+        let debug_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<DebugVertex>() as u64 * 256,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let debug_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let debug_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &debug_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let debug_shader = device.create_shader_module(wgpu::include_wgsl!("./debug.wgsl"));
+        let debug_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&debug_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &debug_shader,
+                entry_point: "vs_debug",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: size_of::<DebugVertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+                }],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &debug_shader,
+                entry_point: "fs_debug",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: scene_texture.format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Ok(Self {
             surface,
             surface_config,
+            offscreen_target,
             device,
             queue,
             shader,
             pipeline,
+            translucent_pipeline,
+            silhouette_pipeline,
             texture_atlas_array,
             bind_group,
             bind_group_layout,
+            anim_shader,
+            anim_bind_group_layout,
+            anim_bind_group,
+            anim_pipeline,
             depth_stencil_texture,
+            depth_stencil_format,
+            scene_texture,
+            blit_shader,
+            blit_bind_group_layout,
+            blit_bind_groups,
+            blit_pipeline,
+            scale_mode,
+            viewport,
             uniform_data,
             start_time,
 
             uniform_buffer,
-            storage_buffer,
+            quad_vertex_buffer,
+            quad_index_buffer,
+            instance_buffer,
+            draw_order_buffer,
             anim_storage_buffer,
-            sorted_sprites: vec![Sprite::empty(); 256],
-        }
+            anim_slots: std::collections::HashMap::new(),
+            free_anim_slots: vec![],
+            next_anim_slot: 0,
+            static_sprites: vec![],
+            raw_keys: vec![],
+            raw_sprites: vec![],
+            sorted_sprites: vec![],
+            sorted_positions: vec![],
+            raw_text_sprites: vec![],
+            text_sprites: vec![],
+            layers,
+            layer_buffer,
+            palettes,
+            palette_texture,
+            tile_bind_group_layout,
+            tile_pipeline,
+            tile_translucent_pipeline,
+            water_mask_pipeline,
+            reflection_pipeline,
+            tile_chunks: std::collections::HashMap::new(),
+            particle_buffer,
+            particle_step_buffer,
+            particle_compute_bind_group,
+            particle_compute_pipeline,
+            particle_bind_group_layout,
+            particle_bind_group,
+            particle_index_buffer,
+            particle_pipeline,
+            particle_translucent_pipeline,
+            next_particle: 0,
+            particle_time: 0.0,
+            particles_alive_until: 0.0,
+            light_texture,
+            light_shader,
+            light_bind_group_layout,
+            light_bind_group,
+            light_pipeline,
+            light_buffer,
+            light_count: 0,
+            ambient: [1.0, 1.0, 1.0],
+            post_texture,
+            post_shader,
+            post_bind_group_layout,
+            post_bind_groups,
+            post_pipelines,
+            post_buffer,
+            lut_texture,
+            post_chain: vec![],
+            fog_drift: 0.0,
+            weather_quality,
+            debug_bind_group,
+            debug_pipeline,
+            debug_buffer,
+            debug_vertex_count: 0,
+        })
     }
 
-    fn update_window_size(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        self.surface_config.height = new_size.height;
-        self.surface_config.width = new_size.width;
-        self.surface.configure(&self.device, &self.surface_config);
-        self.depth_stencil_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+    /// sprites and tiles share everything but the vertex entry point and the bind groups
+    fn create_sprite_pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        vertex_entry_point: &str,
+        pass: SpritePass,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        depth_stencil_format: wgpu::TextureFormat,
+        target_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: vertex_entry_point,
+                buffers: &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: size_of::<[f32; 2]>() as u64,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+                    },
+                    // which sprite or particle each instance is, tiles go by instance_index
+                    // as they're always drawn from 0
+                    wgpu::VertexBufferLayout {
+                        array_stride: size_of::<u32>() as u64,
+                        step_mode: wgpu::VertexStepMode::Instance,
+                        attributes: &wgpu::vertex_attr_array![1 => Uint32],
+                    },
+                ][..match vertex_entry_point {
+                    "vs_tile" => 1,
+                    _ => 2,
+                }],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_stencil_format,
+                depth_write_enabled: pass == SpritePass::Opaque,
+                depth_compare: match pass {
+                    // the sort puts ties in draw order, the later one has to win them
+                    SpritePass::Opaque | SpritePass::Translucent => {
+                        wgpu::CompareFunction::LessEqual
+                    }
+                    SpritePass::Silhouette => wgpu::CompareFunction::Greater,
+                    // only the tile that won the depth test
+                    SpritePass::WaterMask => wgpu::CompareFunction::Equal,
+                    // the opaque sprites drawn after cover it like they cover the water
+                    SpritePass::Reflection => wgpu::CompareFunction::Always,
+                },
+                stencil: match pass {
+                    // the first silhouette to reach a pixel marks it, so overlapping ones
+                    // don't blend over each other
+                    SpritePass::Silhouette => {
+                        let face = wgpu::StencilFaceState {
+                            compare: wgpu::CompareFunction::Equal,
+                            fail_op: wgpu::StencilOperation::Keep,
+                            depth_fail_op: wgpu::StencilOperation::Keep,
+                            pass_op: wgpu::StencilOperation::IncrementClamp,
+                        };
+                        wgpu::StencilState {
+                            front: face,
+                            back: face,
+                            read_mask: WATER_STENCIL - 1,
+                            write_mask: WATER_STENCIL - 1,
+                        }
+                    }
+                    SpritePass::WaterMask => {
+                        let face = wgpu::StencilFaceState {
+                            compare: wgpu::CompareFunction::Always,
+                            fail_op: wgpu::StencilOperation::Keep,
+                            depth_fail_op: wgpu::StencilOperation::Keep,
+                            pass_op: wgpu::StencilOperation::Replace,
+                        };
+                        wgpu::StencilState {
+                            front: face,
+                            back: face,
+                            read_mask: 0,
+                            write_mask: WATER_STENCIL,
+                        }
+                    }
+                    SpritePass::Reflection => {
+                        let face = wgpu::StencilFaceState {
+                            compare: wgpu::CompareFunction::Equal,
+                            fail_op: wgpu::StencilOperation::Keep,
+                            depth_fail_op: wgpu::StencilOperation::Keep,
+                            pass_op: wgpu::StencilOperation::Keep,
+                        };
+                        wgpu::StencilState {
+                            front: face,
+                            back: face,
+                            read_mask: WATER_STENCIL,
+                            write_mask: 0,
+                        }
+                    }
+                    _ => wgpu::StencilState::default(),
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: match pass {
+                    SpritePass::Opaque => "fs_opaque",
+                    SpritePass::Translucent => "fs_translucent",
+                    SpritePass::Silhouette => "fs_silhouette",
+                    SpritePass::WaterMask => "fs_water_mask",
+                    SpritePass::Reflection => "fs_reflection",
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: (pass != SpritePass::Opaque && pass != SpritePass::WaterMask).then_some(
+                        wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::Zero,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        },
+                    ),
+                    write_mask: match pass {
+                        SpritePass::WaterMask => wgpu::ColorWrites::empty(),
+                        _ => wgpu::ColorWrites::ALL,
+                    },
+                })],
+            }),
+            multiview: None,
+        })
+    }
+
+    fn create_offscreen_target(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size: wgpu::Extent3d {
-                width: self.surface_config.width,
-                height: self.surface_config.height,
+                width: surface_config.width,
+                height: surface_config.height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32FloatStencil8,
-            usage: wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_config.format,
+            usage: surface_config.usage,
             view_formats: &[],
-        });
-        self.uniform_data.window_height = new_size.height as _;
-        self.uniform_data.window_width = new_size.width as _;
+        })
     }
 
-    fn update_time(&mut self) {
-        self.uniform_data.utime = self.start_time.elapsed().as_secs_f32();
-        self.uniform_data.delta_time = self.uniform_data.utime - self.uniform_data.last_frame_time;
-        self.uniform_data.last_frame_time = self.uniform_data.utime;
-        self.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniform_data]),
+    fn create_atlas_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_data: &image::RgbaImage,
+    ) -> wgpu::Texture {
+        let texture_atlas_array = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: texture_data.width(),
+                height: texture_data.height(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTextureBase {
+                texture: &texture_atlas_array,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            texture_data.as_bytes(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * texture_data.width()),
+                rows_per_image: Some(texture_data.height()),
+            },
+            texture_atlas_array.size(),
         );
+        texture_atlas_array
     }
 
-    fn load_sprites(&mut self, sprites: &[Sprite]) {
-        if self.sorted_sprites.capacity() < sprites.len() {
-            println!("resizing");
-            self.sorted_sprites.reserve(self.sorted_sprites.len());
-        }
-        unsafe { self.sorted_sprites.set_len(sprites.len()) };
-
-        self.sorted_sprites.clone_from_slice(sprites);
-        self.sorted_sprites.sort_unstable_by(|a, b| {
-            let depth_a = 0.2
-                * (((a.top_left_position_y - a.origin_offset_y)
-                    / self.uniform_data.height_resolution)
-                    + 1.0)
-                / 2.0
-                + a.depth_base;
-            let depth_b = 0.2
-                * (((b.top_left_position_y - b.origin_offset_y)
-                    / self.uniform_data.height_resolution)
-                    + 1.0)
-                / 2.0
-                + b.depth_base;
-            depth_b.total_cmp(&depth_a)
-        });
-
-        let size_needed = sprites.len() * size_of::<Sprite>();
-        if self.storage_buffer.size() < size_needed as u64 {
-            self.storage_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                // size: size_needed as _,
-                size: self.storage_buffer.size() * 2,
-                usage: wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::COPY_SRC
-                    | wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
-            self.anim_storage_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                // size: sprites.len() as u64 * 8,
-                size: self.anim_storage_buffer.size() * 2,
-                usage: wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::COPY_SRC
-                    | wgpu::BufferUsages::STORAGE
-                    | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
-            self.bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &self.bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self
-                                .texture_atlas_array
-                                .create_view(&wgpu::TextureViewDescriptor {
-                                    label: None,
-                                    format: None,
-                                    dimension: None,
-                                    aspect: wgpu::TextureAspect::All,
-                                    base_mip_level: 0,
-                                    mip_level_count: None,
-                                    base_array_layer: 0,
-                                    array_layer_count: None,
-                                }),
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &self.uniform_buffer,
-                            offset: 0,
-                            size: None,
-                        }),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &self.storage_buffer,
-                            offset: 0,
-                            size: None,
-                        }),
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        bindings: &SpriteBindings,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &bindings
+                            .texture_atlas_array
+                            .create_view(&wgpu::TextureViewDescriptor {
+                                label: None,
+                                format: None,
+                                dimension: None,
+                                aspect: wgpu::TextureAspect::All,
+                                base_mip_level: 0,
+                                mip_level_count: None,
+                                base_array_layer: 0,
+                                array_layer_count: None,
+                            }),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: bindings.uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: bindings.instance_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: bindings.anim_storage_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(
+                        &bindings
+                            .light_texture
+                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: bindings.layer_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(
+                        &bindings
+                            .palette_texture
+                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+        })
+    }
+
+    /// after any of the resources in SpriteBindings was swapped for a new one
+    fn create_draw_order_buffer(device: &wgpu::Device, instance_capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<u32>() as u64 * instance_capacity,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn rebuild_bind_group(&mut self) {
+        self.bind_group = Self::create_bind_group(
+            &self.device,
+            &self.bind_group_layout,
+            &SpriteBindings {
+                texture_atlas_array: &self.texture_atlas_array,
+                uniform_buffer: &self.uniform_buffer,
+                instance_buffer: &self.instance_buffer,
+                anim_storage_buffer: &self.anim_storage_buffer,
+                light_texture: &self.light_texture,
+                layer_buffer: &self.layer_buffer,
+                palette_texture: &self.palette_texture,
+            },
+        );
+    }
+
+    /// sized to exactly the registered layers, vs_main clamps ids with its length
+    fn create_layer_buffer(device: &wgpu::Device, layers: &RenderLayers) -> wgpu::Buffer {
+        let layer_data: Vec<LayerData> = (0..layers.0.len() as u32)
+            .map(|id| layers.data(id))
+            .collect();
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&layer_data),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
+
+    /// the index vs_particle gets is the slot plus PARTICLE_CAPACITY times the layer it's
+    /// drawn for, where 0 is every layer and the rest are one past the layer id
+    fn create_particle_index_buffer(device: &wgpu::Device, layers: &RenderLayers) -> wgpu::Buffer {
+        let count = (layers.0.len() + 1) * PARTICLE_CAPACITY;
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&(0..count as u32).collect::<Vec<u32>>()),
+            usage: wgpu::BufferUsages::VERTEX,
+        })
+    }
+
+    fn create_anim_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        instance_buffer: &wgpu::Buffer,
+        anim_storage_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: instance_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: anim_storage_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+        })
+    }
+
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: light_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+        })
+    }
+
+    fn create_blit_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &wgpu::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &source.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            }],
+        })
+    }
+
+    fn create_post_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &wgpu::Texture,
+        post_buffer: &wgpu::Buffer,
+        lut_texture: &wgpu::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &source.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: post_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(
+                        &lut_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+        })
+    }
+
+    /// rebuilds the sprite and tile pipelines from wgsl source, on a compile or validation
+    /// error the pipelines that are already there stay in use
+    fn reload_shader(&mut self, source: &str) -> Result<(), RendererError> {
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+        let [pipeline, translucent_pipeline, silhouette_pipeline, reflection_pipeline] = [
+            ("vs_main", SpritePass::Opaque),
+            ("vs_main", SpritePass::Translucent),
+            ("vs_silhouette", SpritePass::Silhouette),
+            ("vs_reflection", SpritePass::Reflection),
+        ]
+        .map(|(vertex_entry_point, pass)| {
+            Self::create_sprite_pipeline(
+                &self.device,
+                &shader,
+                vertex_entry_point,
+                pass,
+                &[&self.bind_group_layout],
+                self.depth_stencil_format,
+                self.scene_texture.format(),
+            )
+        });
+        let [tile_pipeline, tile_translucent_pipeline, water_mask_pipeline] = [
+            SpritePass::Opaque,
+            SpritePass::Translucent,
+            SpritePass::WaterMask,
+        ]
+        .map(|pass| {
+            Self::create_sprite_pipeline(
+                &self.device,
+                &shader,
+                "vs_tile",
+                pass,
+                &[&self.bind_group_layout, &self.tile_bind_group_layout],
+                self.depth_stencil_format,
+                self.scene_texture.format(),
+            )
+        });
+        let [particle_pipeline, particle_translucent_pipeline] =
+            [SpritePass::Opaque, SpritePass::Translucent].map(|pass| {
+                Self::create_sprite_pipeline(
+                    &self.device,
+                    &shader,
+                    "vs_particle",
+                    pass,
+                    &[&self.bind_group_layout, &self.particle_bind_group_layout],
+                    self.depth_stencil_format,
+                    self.scene_texture.format(),
+                )
+            });
+        if let Some(err) = pollster::block_on(self.device.pop_error_scope()) {
+            return Err(RendererError::ShaderCompile(err.to_string()));
+        }
+        self.shader = shader;
+        self.pipeline = pipeline;
+        self.translucent_pipeline = translucent_pipeline;
+        self.silhouette_pipeline = silhouette_pipeline;
+        self.tile_pipeline = tile_pipeline;
+        self.tile_translucent_pipeline = tile_translucent_pipeline;
+        self.water_mask_pipeline = water_mask_pipeline;
+        self.reflection_pipeline = reflection_pipeline;
+        self.particle_pipeline = particle_pipeline;
+        self.particle_translucent_pipeline = particle_translucent_pipeline;
+        Ok(())
+    }
+
+    fn max_texture_size(&self) -> u32 {
+        self.device.limits().max_texture_dimension_2d
+    }
+
+    /// swaps the atlas for a freshly packed one, see pack_atlas
+    fn load_atlas(&mut self, texture_data: &image::RgbaImage) -> Result<(), AtlasError> {
+        let max = self.max_texture_size();
+        if texture_data.width() > max || texture_data.height() > max {
+            return Err(AtlasError::TooLarge {
+                width: texture_data.width(),
+                height: texture_data.height(),
+                max,
+            });
+        }
+        self.texture_atlas_array =
+            Self::create_atlas_texture(&self.device, &self.queue, texture_data);
+        self.uniform_data.texture_width = texture_data.width() as f32;
+        self.uniform_data.texture_height = texture_data.height() as f32;
+        self.rebuild_bind_group();
+        Ok(())
+    }
+
+    fn update_window_size(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // minimised, a surface can't be configured to nothing so keep the old size until
+        // the window comes back
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.surface_config.height = new_size.height;
+        self.surface_config.width = new_size.width;
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => {
+                self.offscreen_target = Some(Self::create_offscreen_target(
+                    &self.device,
+                    &self.surface_config,
+                ))
+            }
+        }
+        self.uniform_data.window_height = new_size.height as _;
+        self.uniform_data.window_width = new_size.width as _;
+        self.resize_targets();
+        self.viewport = Viewport::new(
+            self.scale_mode,
+            (self.scene_texture.width(), self.scene_texture.height()),
+            (self.surface_config.width, self.surface_config.height),
+        );
+    }
+
+    /// kept even so the centre of the screen lands between two pixels
+    fn target_width(target_height: u32, window_size: (u32, u32)) -> u32 {
+        ((target_height as f32 * window_size.0 as f32 / window_size.1.max(1) as f32) / 2.0)
+            .round()
+            .max(1.0) as u32
+            * 2
+    }
+
+    /// remakes every texture the size of the scene once the window's aspect ratio no longer
+    /// matches it, the height stays at height_resolution
+    fn resize_targets(&mut self) {
+        let target_height = self.scene_texture.height();
+        let target_width = Self::target_width(
+            target_height,
+            (self.surface_config.width, self.surface_config.height),
+        );
+        if target_width == self.scene_texture.width() {
+            return;
+        }
+        let size = wgpu::Extent3d {
+            width: target_width,
+            height: target_height,
+            depth_or_array_layers: 1,
+        };
+        self.scene_texture = Self::resized_texture(&self.device, &self.scene_texture, size);
+        self.post_texture = Self::resized_texture(&self.device, &self.post_texture, size);
+        self.light_texture = Self::resized_texture(&self.device, &self.light_texture, size);
+        self.depth_stencil_texture =
+            Self::resized_texture(&self.device, &self.depth_stencil_texture, size);
+
+        self.blit_bind_groups = [&self.scene_texture, &self.post_texture].map(|texture| {
+            Self::create_blit_bind_group(&self.device, &self.blit_bind_group_layout, texture)
+        });
+        self.rebuild_post_bind_groups();
+        // light_map
+        self.rebuild_bind_group();
+
+        self.uniform_data.target_width = target_width as f32;
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    /// the same kind of texture at another size
+    fn resized_texture(
+        device: &wgpu::Device,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: texture.mip_level_count(),
+            sample_count: texture.sample_count(),
+            dimension: texture.dimension(),
+            format: texture.format(),
+            usage: texture.usage(),
+            view_formats: &[],
+        })
+    }
+
+    fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        self.scale_mode = scale_mode;
+        self.viewport = Viewport::new(
+            self.scale_mode,
+            (self.scene_texture.width(), self.scene_texture.height()),
+            (self.surface_config.width, self.surface_config.height),
+        );
+    }
+
+    fn update_time(&mut self) {
+        self.set_time(self.start_time.elapsed().as_secs_f32());
+    }
+
+    /// seconds since start_time, update_time does this with the clock
+    fn set_time(&mut self, utime: f32) {
+        self.uniform_data.utime = utime;
+        self.uniform_data.delta_time = self.uniform_data.utime - self.uniform_data.last_frame_time;
+        self.uniform_data.last_frame_time = self.uniform_data.utime;
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    fn update_camera(&mut self, camera: &Camera) {
+        self.uniform_data.camera_x = camera.position.0;
+        self.uniform_data.camera_y = camera.position.1;
+        self.uniform_data.zoom = camera.zoom;
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    /// sways flagged sprites in vs_main and carries the fog along, called every event like
+    /// update_time
+    fn set_wind(&mut self, wind: f32) {
+        self.fog_drift += wind * 12.0 * self.uniform_data.delta_time;
+        self.uniform_data.wind = wind;
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    /// back to front by layer and then by the layer's sort mode, ties go to the load order so
+    /// the order is total and stays the same between frames. vs_main works out a depth that
+    /// agrees with this and equal depths let the later sprite through
+    fn depth_order(a: &Sprite, b: &Sprite, layers: &RenderLayers) -> std::cmp::Ordering {
+        let layer_a = layers.clamp(a.layer);
+        let layer_b = layers.clamp(b.layer);
+        layer_b
+            .cmp(&layer_a)
+            .then_with(|| match layers.0[layer_a as usize].sort {
+                SortMode::YSort => (b.top_left_position_y - b.origin_offset_y)
+                    .total_cmp(&(a.top_left_position_y - a.origin_offset_y)),
+                SortMode::ZIndex => a.z_index.cmp(&b.z_index),
+                SortMode::Insertion => std::cmp::Ordering::Equal,
+            })
+            .then(a.order.cmp(&b.order))
+    }
+
+    /// makes room for `count` instances, keeping what's already in the buffers
+    fn reserve_instances(&mut self, count: usize) {
+        let size_needed = count * size_of::<Sprite>();
+        if self.instance_buffer.size() >= size_needed as u64 {
+            return;
+        }
+        // instances and anim slots always grow together so every instance has a slot
+        let instance_capacity = count.next_power_of_two() as u64;
+        println!("resizing to {instance_capacity} instances");
+        let instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<Sprite>() as u64 * instance_capacity,
+            usage: wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let anim_storage_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<Animation>() as u64 * instance_capacity,
+            usage: wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // carry the uploaded sprites and running animations over to the bigger buffers
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(
+            &self.instance_buffer,
+            0,
+            &instance_buffer,
+            0,
+            self.instance_buffer.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.anim_storage_buffer,
+            0,
+            &anim_storage_buffer,
+            0,
+            self.anim_storage_buffer.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+        self.instance_buffer = instance_buffer;
+        self.anim_storage_buffer = anim_storage_buffer;
+        // written from scratch every frame, nothing to carry over
+        self.draw_order_buffer = Self::create_draw_order_buffer(&self.device, instance_capacity);
+        self.anim_bind_group = Self::create_anim_bind_group(
+            &self.device,
+            &self.anim_bind_group_layout,
+            &self.uniform_buffer,
+            &self.instance_buffer,
+            &self.anim_storage_buffer,
+        );
+        self.rebuild_bind_group();
+    }
+
+    /// for sprites that never move, like scenery props, they're sorted and uploaded here once
+    /// and skipped by the per tick diff in load_sprites
+    fn load_static_sprites(&mut self, sprites: &[Sprite]) {
+        let old_count = self.static_sprites.len();
+        self.static_sprites.clear();
+        self.static_sprites.extend_from_slice(sprites);
+        for (index, sprite) in self.static_sprites.iter_mut().enumerate() {
+            sprite.anim_index = index as u32;
+            sprite.order = index as u32;
+        }
+        let layers = &self.layers;
+        self.static_sprites
+            .sort_unstable_by(|a, b| Self::depth_order(a, b, layers));
+
+        // the dynamic sprites' slots move along with the end of the static ones
+        let new_count = self.static_sprites.len();
+        let dynamic_slots = self.raw_sprites.len().max(self.next_anim_slot as usize);
+        self.reserve_instances(new_count + dynamic_slots);
+        if new_count != old_count && dynamic_slots > 0 {
+            // a buffer can't be copied onto itself, so they go through a scratch one
+            let size = (dynamic_slots * size_of::<Animation>()) as u64;
+            let scratch = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            encoder.copy_buffer_to_buffer(
+                &self.anim_storage_buffer,
+                (old_count * size_of::<Animation>()) as u64,
+                &scratch,
+                0,
+                size,
+            );
+            encoder.copy_buffer_to_buffer(
+                &scratch,
+                0,
+                &self.anim_storage_buffer,
+                (new_count * size_of::<Animation>()) as u64,
+                size,
+            );
+            self.queue.submit(Some(encoder.finish()));
+        }
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.static_sprites),
+        );
+        // only the static slots start over, the moved ones are past them
+        if new_count > 0 {
+            self.queue.write_buffer(
+                &self.anim_storage_buffer,
+                0,
+                bytemuck::cast_slice(&vec![Animation::zeroed(); new_count]),
+            );
+        }
+        let raw_keys = std::mem::take(&mut self.raw_keys);
+        let raw_sprites = std::mem::take(&mut self.raw_sprites);
+        self.rebuild_sprites(&raw_keys, &raw_sprites);
+    }
+
+    /// diffs `sprites` against the last call and only re-sorts and re-uploads what changed.
+    /// `keys` tells the sprites apart, a sprite keeps its anim slot for as long as its key
+    /// shows up here. keys mustn't be handed to another sprite, see SpawnedSprite::serial
+    fn load_sprites(&mut self, keys: &[u64], sprites: &[Sprite]) {
+        assert_eq!(keys.len(), sprites.len(), "one key per sprite");
+        // something was inserted or removed, the query order no longer lines up with the
+        // snapshot so everything gets sorted again
+        if keys != self.raw_keys.as_slice() {
+            self.rebuild_sprites(keys, sprites);
+            return;
+        }
+
+        let static_count = self.static_sprites.len();
+        let mut dirty: Option<std::ops::Range<usize>> = None;
+        for (index, sprite) in sprites.iter().enumerate() {
+            let mut sprite = *sprite;
+            sprite.anim_index = self.raw_sprites[index].anim_index;
+            sprite.order = (static_count + index) as u32;
+            if bytemuck::bytes_of(&sprite) == bytemuck::bytes_of(&self.raw_sprites[index]) {
+                continue;
+            }
+            self.raw_sprites[index] = sprite;
+
+            // pull it out and put it back where it belongs now, everything in between
+            // shifts over by one
+            let old_position = self.sorted_positions[index];
+            self.sorted_sprites.remove(old_position);
+            let new_position = self.sorted_sprites.partition_point(|other| {
+                Self::depth_order(other, &sprite, &self.layers) == std::cmp::Ordering::Less
+            });
+            self.sorted_sprites.insert(new_position, sprite);
+
+            let changed = old_position.min(new_position)..old_position.max(new_position) + 1;
+            for position in changed.clone() {
+                let raw_index = self.sorted_sprites[position].order as usize - static_count;
+                self.sorted_positions[raw_index] = position;
+            }
+            dirty = Some(match dirty {
+                Some(dirty) => dirty.start.min(changed.start)..dirty.end.max(changed.end),
+                None => changed,
+            });
+        }
+
+        if let Some(dirty) = dirty {
+            self.queue.write_buffer(
+                &self.instance_buffer,
+                ((static_count + dirty.start) * size_of::<Sprite>()) as u64,
+                bytemuck::cast_slice(&self.sorted_sprites[dirty]),
+            );
+        }
+    }
+
+    fn rebuild_sprites(&mut self, keys: &[u64], sprites: &[Sprite]) {
+        let static_count = self.static_sprites.len();
+
+        // sprites that are gone give their slot back. it's reset so the next sprite to take
+        // it starts from frame 0
+        let loaded: std::collections::HashSet<u64> = keys.iter().copied().collect();
+        let mut released = vec![];
+        self.anim_slots.retain(|key, slot| {
+            let keep = loaded.contains(key);
+            if !keep {
+                released.push(*slot);
+            }
+            keep
+        });
+        for slot in released {
+            self.queue.write_buffer(
+                &self.anim_storage_buffer,
+                ((static_count + slot as usize) * size_of::<Animation>()) as u64,
+                bytemuck::bytes_of(&Animation::zeroed()),
+            );
+            self.free_anim_slots.push(slot);
+        }
+        for key in keys {
+            if !self.anim_slots.contains_key(key) {
+                let slot = self.free_anim_slots.pop().unwrap_or_else(|| {
+                    self.next_anim_slot += 1;
+                    self.next_anim_slot - 1
+                });
+                self.anim_slots.insert(*key, slot);
+            }
+        }
+
+        self.raw_keys.clear();
+        self.raw_keys.extend_from_slice(keys);
+        self.raw_sprites.clear();
+        self.raw_sprites.extend_from_slice(sprites);
+        // the anim slot follows the key rather than the sorted position or the query order,
+        // so a sprite keeps its frame state when others come and go
+        for (index, sprite) in self.raw_sprites.iter_mut().enumerate() {
+            sprite.anim_index = static_count as u32 + self.anim_slots[&keys[index]];
+            sprite.order = (static_count + index) as u32;
+        }
+        self.sorted_sprites.clear();
+        self.sorted_sprites.extend_from_slice(&self.raw_sprites);
+        let layers = &self.layers;
+        self.sorted_sprites
+            .sort_unstable_by(|a, b| Self::depth_order(a, b, layers));
+        self.sorted_positions.resize(sprites.len(), 0);
+        for (position, sprite) in self.sorted_sprites.iter().enumerate() {
+            self.sorted_positions[sprite.order as usize - static_count] = position;
+        }
+
+        self.reserve_instances(static_count + sprites.len().max(self.next_anim_slot as usize));
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            (static_count * size_of::<Sprite>()) as u64,
+            bytemuck::cast_slice(&self.sorted_sprites),
+        );
+
+        // the text run starts right after the dynamic sprites, so it moves with their count
+        self.write_text_sprites();
+    }
+
+    /// text changes length from one frame to the next, keeping it apart leaves the world
+    /// sprites' count alone so load_sprites can keep diffing them
+    fn load_text_sprites(&mut self, sprites: &[Sprite]) {
+        if bytemuck::cast_slice::<Sprite, u8>(sprites)
+            == bytemuck::cast_slice::<Sprite, u8>(&self.raw_text_sprites)
+        {
+            return;
+        }
+        self.raw_text_sprites.clear();
+        self.raw_text_sprites.extend_from_slice(sprites);
+        self.write_text_sprites();
+    }
+
+    /// numbered and sorted afresh every time, the run starts wherever the dynamic sprites end
+    /// and the orders have to follow it
+    fn write_text_sprites(&mut self) {
+        // glyphs never animate, so they don't need an anim slot
+        let start = self.static_sprites.len() + self.raw_sprites.len();
+        self.text_sprites.clear();
+        self.text_sprites.extend_from_slice(&self.raw_text_sprites);
+        for (index, sprite) in self.text_sprites.iter_mut().enumerate() {
+            sprite.order = (start + index) as u32;
+        }
+        let layers = &self.layers;
+        self.text_sprites
+            .sort_unstable_by(|a, b| Self::depth_order(a, b, layers));
+        let sprite_count = start + self.text_sprites.len();
+        self.reserve_instances(sprite_count);
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            (start * size_of::<Sprite>()) as u64,
+            bytemuck::cast_slice(&self.text_sprites),
+        );
+
+        self.uniform_data.sprite_count = sprite_count as u32;
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform_data]),
+        );
+    }
+
+    /// swaps the layer registry, everything already loaded is sorted again and every tile
+    /// chunk moves to its layer's new depth. an empty registry is turned down and the old one
+    /// stays
+    fn set_layers(&mut self, layers: &RenderLayers) -> Result<(), RendererError> {
+        if layers.0.is_empty() {
+            return Err(RendererError::NoLayers);
+        }
+        self.layers = layers.clone();
+        self.layer_buffer = Self::create_layer_buffer(&self.device, &self.layers);
+        self.particle_index_buffer = Self::create_particle_index_buffer(&self.device, &self.layers);
+        self.rebuild_bind_group();
+
+        // static sprites keep their anim slots, only their order changes
+        let layers = &self.layers;
+        self.static_sprites
+            .sort_unstable_by(|a, b| Self::depth_order(a, b, layers));
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.static_sprites),
+        );
+        // glyphs are sorted again at the end of rebuild_sprites
+        let raw_keys = std::mem::take(&mut self.raw_keys);
+        let raw_sprites = std::mem::take(&mut self.raw_sprites);
+        self.rebuild_sprites(&raw_keys, &raw_sprites);
+
+        for (&(layer, _, _), buffers) in self.tile_chunks.iter() {
+            let chunk_data = ChunkData {
+                origin_x: buffers.origin.0,
+                origin_y: buffers.origin.1,
+                depth: self.layers.data(layer).tile_depth,
+                tile_size: buffers.extent / CHUNK_SIZE as f32,
+            };
+            self.queue.write_buffer(
+                &buffers.chunk_buffer,
+                0,
+                bytemuck::cast_slice(&[chunk_data]),
+            );
+        }
+        Ok(())
+    }
+
+    /// a strip of n slices of n by n, blue picks the slice, red goes across and green down
+    fn load_lut(&mut self, lut: &image::RgbaImage) -> Result<(), RendererError> {
+        if lut.height() < 2 || lut.width() != lut.height() * lut.height() {
+            return Err(RendererError::InvalidLut {
+                width: lut.width(),
+                height: lut.height(),
+            });
+        }
+        self.lut_texture = Self::create_atlas_texture(&self.device, &self.queue, lut);
+        self.rebuild_post_bind_groups();
+        Ok(())
+    }
+
+    fn rebuild_post_bind_groups(&mut self) {
+        self.post_bind_groups = [
+            Self::create_post_bind_group(
+                &self.device,
+                &self.post_bind_group_layout,
+                &self.scene_texture,
+                &self.post_buffer,
+                &self.lut_texture,
+            ),
+            Self::create_post_bind_group(
+                &self.device,
+                &self.post_bind_group_layout,
+                &self.post_texture,
+                &self.post_buffer,
+                &self.lut_texture,
+            ),
+        ];
+    }
+
+    /// row 0 of the image lists the colours to swap, each row below is one palette with the
+    /// replacement for every source colour in the same column
+    fn load_palettes(
+        &mut self,
+        image: &image::RgbaImage,
+        palettes: Palettes,
+    ) -> Result<(), RendererError> {
+        let rows = palettes.0.len() as u32 + 1;
+        if image.width() == 0 || image.height() < rows {
+            return Err(RendererError::InvalidPalette {
+                height: image.height(),
+                rows,
+            });
+        }
+        self.palettes = palettes;
+        self.palette_texture = Self::create_atlas_texture(&self.device, &self.queue, image);
+        self.rebuild_bind_group();
+        Ok(())
+    }
+
+    fn set_post_effects(&mut self, effects: &PostEffects) {
+        self.post_chain.clear();
+        let mut post_data = PostData {
+            // the fog is pinned to the world and carried by the wind, so where it is sampled
+            // from moves the other way
+            fog_origin_x: self.uniform_data.camera_x - self.fog_drift,
+            fog_origin_y: self.uniform_data.camera_y,
+            fog_zoom: self.uniform_data.zoom,
+            ..PostData::zeroed()
+        };
+        // at 0 an effect does nothing, so its pass is skipped
+        for effect in effects
+            .0
+            .iter()
+            .filter(|effect| effect.enabled && effect.amount > 0.0)
+        {
+            self.post_chain.push(effect.kind);
+            match effect.kind {
+                PostEffectKind::ColorGrade => post_data.color_grade = effect.amount,
+                PostEffectKind::Vignette => post_data.vignette = effect.amount,
+                PostEffectKind::Fade => post_data.fade = effect.amount,
+                PostEffectKind::Desaturate => post_data.desaturate = effect.amount,
+                PostEffectKind::Fog => {
+                    post_data.fog = effect.amount;
+                    post_data.fog_octaves = self.weather_quality.fog_octaves();
+                }
+            }
+        }
+        self.queue
+            .write_buffer(&self.post_buffer, 0, bytemuck::cast_slice(&[post_data]));
+    }
+
+    /// the light map is cleared to `ambient` and every light is added on top of it
+    fn load_lights(&mut self, ambient: [f32; 3], lights: &[LightInstance]) {
+        self.ambient = ambient;
+        let size_needed = std::mem::size_of_val(lights) as u64;
+        if self.light_buffer.size() < size_needed {
+            self.light_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: size_of::<LightInstance>() as u64 * lights.len().next_power_of_two() as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+            self.light_bind_group = Self::create_light_bind_group(
+                &self.device,
+                &self.light_bind_group_layout,
+                &self.uniform_buffer,
+                &self.light_buffer,
+            );
+        }
+        self.queue
+            .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(lights));
+        self.light_count = lights.len() as u32;
+    }
+
+    /// `particles` come aged to the current utime, past the capacity the oldest get
+    /// written over
+    fn spawn_particles(&mut self, particles: &[Particle]) {
+        if particles.is_empty() {
+            return;
+        }
+        let particles = &particles[particles.len().saturating_sub(PARTICLE_CAPACITY)..];
+        // the next step starts from particle_time, so the ages are wound back to it
+        let behind = self.uniform_data.utime - self.particle_time;
+        let particles: Vec<Particle> = particles
+            .iter()
+            .map(|particle| {
+                self.particles_alive_until = self
+                    .particles_alive_until
+                    .max(self.uniform_data.utime + particle.lifetime - particle.age);
+                Particle {
+                    age: particle.age - behind,
+                    ..*particle
+                }
+            })
+            .collect();
+        let until_end = (PARTICLE_CAPACITY - self.next_particle).min(particles.len());
+        self.queue.write_buffer(
+            &self.particle_buffer,
+            (self.next_particle * size_of::<Particle>()) as u64,
+            bytemuck::cast_slice(&particles[..until_end]),
+        );
+        if until_end < particles.len() {
+            self.queue.write_buffer(
+                &self.particle_buffer,
+                0,
+                bytemuck::cast_slice(&particles[until_end..]),
+            );
+        }
+        self.next_particle = (self.next_particle + particles.len()) % PARTICLE_CAPACITY;
+    }
+
+    /// replaces last frame's lines, pairs of vertices make up a line
+    fn load_debug_lines(&mut self, vertices: &[DebugVertex]) {
+        self.debug_vertex_count = vertices.len() as u32;
+        if vertices.is_empty() {
+            return;
+        }
+        let size_needed = std::mem::size_of_val(vertices) as u64;
+        if self.debug_buffer.size() < size_needed {
+            self.debug_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: size_of::<DebugVertex>() as u64 * vertices.len().next_power_of_two() as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+                mapped_at_creation: false,
+            });
+        }
+        self.queue
+            .write_buffer(&self.debug_buffer, 0, bytemuck::cast_slice(vertices));
+    }
+
+    /// uploads the chunks that were edited since the last call, chunks that were removed from
+    /// the tilemap are dropped here as well
+    fn load_tilemap(&mut self, tilemap: &mut Tilemap) {
+        self.tile_chunks
+            .retain(|key, _| tilemap.chunks.contains_key(key));
+        for (&(layer, chunk_x, chunk_y), chunk) in tilemap.chunks.iter_mut() {
+            if !chunk.dirty {
+                continue;
+            }
+            chunk.dirty = false;
+
+            let extent = (CHUNK_SIZE as u32 * tilemap.tile_size) as f32;
+            let chunk_data = ChunkData {
+                origin_x: chunk_x as f32 * extent,
+                origin_y: chunk_y as f32 * extent,
+                depth: self.layers.data(layer).tile_depth,
+                tile_size: tilemap.tile_size as f32,
+            };
+            let tiles: Vec<TileInstance> = chunk
+                .tiles
+                .iter()
+                .map(|&id| match id {
+                    0 => TileInstance::zeroed(),
+                    id => {
+                        let tile = &tilemap.tileset[id as usize - 1];
+                        TileInstance {
+                            top_left_tex_coords_x: tile.top_left_tex_pos.0 as _,
+                            top_left_tex_coords_y: tile.top_left_tex_pos.1 as _,
+                            visible: 1,
+                            material: tile.material as u32,
+                            frame_count: tile.frame_count,
+                            frame_interval: tile.frame_interval,
+                            _padding: [0; 2],
+                        }
+                    }
+                })
+                .collect();
+
+            let device = &self.device;
+            let layout = &self.tile_bind_group_layout;
+            let buffers = self
+                .tile_chunks
+                .entry((layer, chunk_x, chunk_y))
+                .or_insert_with(|| Self::create_chunk_buffers(device, layout));
+            buffers.origin = (chunk_data.origin_x, chunk_data.origin_y);
+            buffers.extent = extent;
+            buffers.water = tiles
+                .iter()
+                .any(|tile| tile.visible != 0 && tile.material == TileMaterial::Water as u32);
+            self.queue.write_buffer(
+                &buffers.chunk_buffer,
+                0,
+                bytemuck::cast_slice(&[chunk_data]),
+            );
+            self.queue
+                .write_buffer(&buffers.tile_buffer, 0, bytemuck::cast_slice(&tiles));
+        }
+    }
+
+    fn create_chunk_buffers(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> ChunkBuffers {
+        let chunk_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<ChunkData>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        let tile_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (size_of::<TileInstance>() * CHUNK_SIZE * CHUNK_SIZE) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &chunk_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &tile_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+        });
+        ChunkBuffers {
+            chunk_buffer,
+            tile_buffer,
+            bind_group,
+            origin: (0.0, 0.0),
+            extent: 0.0,
+            water: false,
+        }
+    }
+
+    /// a lost or outdated surface is reconfigured and the frame skipped, only running out
+    /// of memory is handed back as an error
+    fn render(&mut self) -> Result<(), RendererError> {
+        match &self.surface {
+            Some(surface) => {
+                let canvas = match surface.get_current_texture() {
+                    Ok(canvas) => canvas,
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        surface.configure(&self.device, &self.surface_config);
+                        return Ok(());
+                    }
+                    Err(wgpu::SurfaceError::Timeout) => return Ok(()),
+                    Err(err) => return Err(RendererError::Surface(err)),
+                };
+                let canvas_view = canvas
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                self.step_particles();
+                self.draw(&canvas_view);
+                canvas.present();
+            }
+            None => {
+                let canvas_view = self
+                    .offscreen_target
+                    .as_ref()
+                    .unwrap()
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                self.step_particles();
+                self.draw(&canvas_view);
+            }
+        }
+        Ok(())
+    }
+
+    /// particles are stepped once a frame however many events came in since the last one, and
+    /// only for frames that get drawn so a skipped one doesn't lose its time
+    fn step_particles(&mut self) {
+        let particle_step = ParticleStep {
+            delta_time: self.uniform_data.utime - self.particle_time,
+            _padding: [0; 3],
+        };
+        self.particle_time = self.uniform_data.utime;
+        self.queue.write_buffer(
+            &self.particle_step_buffer,
+            0,
+            bytemuck::cast_slice(&[particle_step]),
+        );
+    }
+
+    fn draw(&self, canvas_view: &wgpu::TextureView) {
+        let depth_view = self
+            .depth_stencil_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        let mut compute_pass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        compute_pass.set_pipeline(&self.anim_pipeline);
+        compute_pass.set_bind_group(0, &self.anim_bind_group, &[]);
+        compute_pass.dispatch_workgroups((self.uniform_data.sprite_count + 63) / 64, 1, 1);
+        if self.particles_alive() {
+            compute_pass.set_pipeline(&self.particle_compute_pipeline);
+            compute_pass.set_bind_group(0, &self.particle_compute_bind_group, &[]);
+            compute_pass.dispatch_workgroups((PARTICLE_CAPACITY as u32 + 63) / 64, 1, 1);
+        }
+        drop(compute_pass);
+
+        let light_view = self
+            .light_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut light_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &light_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: self.ambient[0] as f64,
+                        g: self.ambient[1] as f64,
+                        b: self.ambient[2] as f64,
+                        a: 1.0,
+                    }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        if self.light_count > 0 {
+            light_pass.set_pipeline(&self.light_pipeline);
+            light_pass.set_bind_group(0, &self.light_bind_group, &[]);
+            light_pass.set_vertex_buffer(0, self.quad_vertex_buffer.slice(..));
+            light_pass
+                .set_index_buffer(self.quad_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            light_pass.draw_indexed(0..6, 0, 0..self.light_count);
+        }
+        drop(light_pass);
+
+        let scene_view = self
+            .scene_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &scene_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::RED),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+        let layer_draws = self.write_draw_order();
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.quad_vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.quad_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_pipeline(&self.tile_pipeline);
+        self.draw_tiles(&mut render_pass, None);
+        // reflections go in before the sprites so those cover them like they cover the water
+        if self.tile_chunks.values().any(|buffers| buffers.water) {
+            render_pass.set_stencil_reference(WATER_STENCIL);
+            render_pass.set_pipeline(&self.water_mask_pipeline);
+            self.draw_tiles(&mut render_pass, None);
+            render_pass.set_pipeline(&self.reflection_pipeline);
+            self.draw_sprites(&mut render_pass, 0..self.uniform_data.sprite_count);
+            render_pass.set_stencil_reference(0);
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        self.draw_sprites(&mut render_pass, 0..self.uniform_data.sprite_count);
+        render_pass.set_pipeline(&self.particle_pipeline);
+        self.draw_particles(&mut render_pass, None);
+        // every opaque texel is in the depth buffer by now, so the half transparent ones can
+        // blend back to front over whatever ended up behind them. a layer is done before the
+        // one in front of it, and its tiles go first as they sit behind its sprites. particles
+        // aren't sorted among the sprites, where they overlap a blended sprite of the same
+        // layer the particle ends up on top
+        for layer in (0..self.layers.0.len() as u32).rev() {
+            render_pass.set_pipeline(&self.tile_translucent_pipeline);
+            self.draw_tiles(&mut render_pass, Some(layer));
+            render_pass.set_pipeline(&self.translucent_pipeline);
+            self.draw_sprites(&mut render_pass, layer_draws[layer as usize].clone());
+            render_pass.set_pipeline(&self.particle_translucent_pipeline);
+            self.draw_particles(&mut render_pass, Some(layer));
+        }
+        render_pass.set_pipeline(&self.silhouette_pipeline);
+        self.draw_sprites(&mut render_pass, 0..self.uniform_data.sprite_count);
+        drop(render_pass);
+
+        // every effect reads what the one before it wrote, 0 is scene_texture and 1 post_texture
+        let mut source = 0;
+        for kind in self.post_chain.iter() {
+            let target = match source {
+                0 => &self.post_texture,
+                _ => &self.scene_texture,
+            };
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut post_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
                     },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &self.anim_storage_buffer,
-                            offset: 0,
-                            size: None,
-                        }),
+                })],
+                depth_stencil_attachment: None,
+            });
+            post_pass.set_pipeline(&self.post_pipelines[*kind as usize]);
+            post_pass.set_bind_group(0, &self.post_bind_groups[source], &[]);
+            post_pass.draw(0..3, 0..1);
+            drop(post_pass);
+            source = 1 - source;
+        }
+
+        // after the post effects so the lines keep their colour under the grading and fade
+        if self.debug_vertex_count > 0 {
+            let target = match source {
+                0 => &self.scene_texture,
+                _ => &self.post_texture,
+            };
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut debug_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
                     },
-                ],
+                })],
+                depth_stencil_attachment: None,
+            });
+            debug_pass.set_pipeline(&self.debug_pipeline);
+            debug_pass.set_bind_group(0, &self.debug_bind_group, &[]);
+            debug_pass.set_vertex_buffer(0, self.debug_buffer.slice(..));
+            debug_pass.draw(0..self.debug_vertex_count, 0..1);
+            drop(debug_pass);
+        }
+
+        // scale the scene up onto the window, whatever the viewport leaves uncovered are the bars
+        let mut blit_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: canvas_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        blit_pass.set_viewport(
+            self.viewport.x,
+            self.viewport.y,
+            self.viewport.width,
+            self.viewport.height,
+            0.0,
+            1.0,
+        );
+        blit_pass.set_pipeline(&self.blit_pipeline);
+        blit_pass.set_bind_group(0, &self.blit_bind_groups[source], &[]);
+        blit_pass.draw(0..3, 0..1);
+        drop(blit_pass);
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// one draw per chunk that overlaps the view, empty cells are collapsed in vs_tile. with
+    /// a layer only that layer's chunks are drawn
+    fn draw_tiles<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, layer: Option<u32>) {
+        let half_width = self.uniform_data.target_width / 2.0 / self.uniform_data.zoom;
+        let half_height = self.uniform_data.target_height / 2.0 / self.uniform_data.zoom;
+        for (&(chunk_layer, _, _), buffers) in self.tile_chunks.iter() {
+            if layer.is_some_and(|layer| self.layers.clamp(chunk_layer) != layer) {
+                continue;
+            }
+            if buffers.origin.0 > self.uniform_data.camera_x + half_width
+                || buffers.origin.0 + buffers.extent < self.uniform_data.camera_x - half_width
+                || buffers.origin.1 > self.uniform_data.camera_y + half_height
+                || buffers.origin.1 + buffers.extent < self.uniform_data.camera_y - half_height
+            {
+                continue;
+            }
+            render_pass.set_bind_group(1, &buffers.bind_group, &[]);
+            render_pass.draw_indexed(0..6, 0, 0..(CHUNK_SIZE * CHUNK_SIZE) as u32);
+        }
+    }
+
+    fn particles_alive(&self) -> bool {
+        self.particle_time < self.particles_alive_until
+    }
+
+    /// every slot of the ring, dead ones are collapsed in vs_particle. with a layer the
+    /// other layers' particles are collapsed too
+    fn draw_particles<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, layer: Option<u32>) {
+        if self.particles_alive() {
+            let filter = layer.map_or(0, |layer| layer + 1);
+            let start = filter * PARTICLE_CAPACITY as u32;
+            render_pass.set_bind_group(1, &self.particle_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, self.particle_index_buffer.slice(..));
+            render_pass.draw_indexed(0..6, 0, start..start + PARTICLE_CAPACITY as u32);
+        }
+    }
+
+    /// merges the static, dynamic and text runs back to front into draw_order_buffer, each
+    /// is sorted on its own. returns where every layer's stretch of it starts and ends
+    fn write_draw_order(&self) -> Vec<std::ops::Range<u32>> {
+        let runs = [
+            &self.static_sprites,
+            &self.sorted_sprites,
+            &self.text_sprites,
+        ];
+        let mut offsets = [0; 3];
+        for run in 1..runs.len() {
+            offsets[run] = offsets[run - 1] + runs[run - 1].len();
+        }
+        let mut cursors = [0; 3];
+        let mut draw_order = Vec::with_capacity(self.uniform_data.sprite_count as usize);
+        let mut layer_draws = vec![0..0; self.layers.0.len()];
+        while let Some(run) = (0..runs.len())
+            .filter(|&run| cursors[run] < runs[run].len())
+            .min_by(|&a, &b| {
+                Self::depth_order(&runs[a][cursors[a]], &runs[b][cursors[b]], &self.layers)
+            })
+        {
+            // layers come out one after another since depth_order goes by layer first
+            let layer = self.layers.clamp(runs[run][cursors[run]].layer) as usize;
+            let position = draw_order.len() as u32;
+            if layer_draws[layer].is_empty() {
+                layer_draws[layer].start = position;
+            }
+            layer_draws[layer].end = position + 1;
+            draw_order.push((offsets[run] + cursors[run]) as u32);
+            cursors[run] += 1;
+        }
+        self.queue.write_buffer(
+            &self.draw_order_buffer,
+            0,
+            bytemuck::cast_slice(&draw_order),
+        );
+        layer_draws
+    }
+
+    /// draws a stretch of draw_order_buffer, any pipeline or atlas switch has to happen
+    /// between two of these
+    fn draw_sprites<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        instances: std::ops::Range<u32>,
+    ) {
+        if !instances.is_empty() {
+            render_pass.set_vertex_buffer(1, self.draw_order_buffer.slice(..));
+            render_pass.draw_indexed(0..6, 0, instances);
+        }
+    }
+
+    /// copies the last headless frame back from the gpu, letterbox and all
+    fn read_frame(&self) -> image::RgbaImage {
+        self.read_texture(
+            self.offscreen_target
+                .as_ref()
+                .expect("read_frame is only available on a headless renderer"),
+        )
+    }
+
+    /// the last frame at scene resolution, after the post effects but before it's scaled up
+    fn read_scene(&self) -> image::RgbaImage {
+        // the chain flips between the two on every effect
+        match self.post_chain.len() % 2 {
+            0 => self.read_texture(&self.scene_texture),
+            _ => self.read_texture(&self.post_texture),
+        }
+    }
+
+    /// rows are unpadded before returning, only for rgba8 textures
+    fn read_texture(&self, texture: &wgpu::Texture) -> image::RgbaImage {
+        let unpadded_bytes_per_row = 4 * texture.width();
+        let padded_bytes_per_row = (unpadded_bytes_per_row + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+            - 1)
+            / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_bytes_per_row * texture.height()) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(texture.height()),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        self.device.poll(wgpu::Maintain::Wait);
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * texture.height()) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(texture.width(), texture.height(), pixels).unwrap()
+    }
+}
+
+// ------------------------------------------------------------------ //
+/// somewhere to put a screenshot or recording, captures/<name>_<milliseconds since epoch>
+fn capture_path(name: &str, extension: &str) -> std::io::Result<std::path::PathBuf> {
+    let dir = std::env::current_dir()?.join("captures");
+    std::fs::create_dir_all(&dir)?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    Ok(dir.join(format!("{name}_{timestamp}.{extension}")))
+}
+
+/// frames grabbed with read_scene for an animated gif
+struct Recording {
+    frames: Vec<image::Frame>,
+    max_frames: usize,
+    last_frame: std::time::Instant,
+}
+impl Recording {
+    // gif delays are in hundredths of a second and most viewers slow anything under 2 down
+    const MIN_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+    fn new(max_frames: usize) -> Self {
+        Self {
+            frames: vec![],
+            max_frames,
+            last_frame: std::time::Instant::now(),
+        }
+    }
+
+    fn wants_frame(&self) -> bool {
+        self.frames.is_empty() || self.last_frame.elapsed() >= Self::MIN_DELAY
+    }
+
+    /// each frame is shown until the next one was taken, returns true once it's full
+    fn push(&mut self, frame: image::RgbaImage) -> bool {
+        let now = std::time::Instant::now();
+        if let Some(previous) = self.frames.pop() {
+            let delay = image::Delay::from_saturating_duration(now - self.last_frame);
+            let buffer = previous.into_buffer();
+            self.frames
+                .push(image::Frame::from_parts(buffer, 0, 0, delay));
+        }
+        self.frames.push(image::Frame::new(frame));
+        self.last_frame = now;
+        self.frames.len() >= self.max_frames
+    }
+
+    /// encoding takes a while so it happens on its own thread
+    fn save(mut self, path: std::path::PathBuf) {
+        if let Some(last) = self.frames.pop() {
+            let delay = image::Delay::from_saturating_duration(
+                self.last_frame.elapsed().max(Self::MIN_DELAY),
+            );
+            self.frames
+                .push(image::Frame::from_parts(last.into_buffer(), 0, 0, delay));
+        }
+        std::thread::spawn(move || {
+            let result = std::fs::File::create(&path)
+                .map_err(image::ImageError::IoError)
+                .and_then(|file| {
+                    let mut encoder = image::codecs::gif::GifEncoder::new(file);
+                    encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+                    encoder.encode_frames(self.frames)
+                });
+            match result {
+                Ok(()) => println!("saved {}", path.display()),
+                Err(err) => eprintln!("could not save {}: {err}", path.display()),
+            }
+        });
+    }
+}
+
+// ------------------------------------------------------------------ //
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortMode {
+    // by the y of the sprite's origin, lower is further in front
+    YSort,
+    // by Sprite::z_index, higher is further in front
+    ZIndex,
+    // in the order the sprites were handed to the renderer, later is further in front
+    Insertion,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct RenderLayer {
+    name: String,
+    sort: SortMode,
+}
+
+/// a layer as vs_main sees it, the depth buffer is split into one band per layer
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct LayerData {
+    depth_base: f32,
+    // sprites are spread over this much of the band by their sort key
+    depth_range: f32,
+    // just behind every sprite of the layer, still in front of the next layer
+    tile_depth: f32,
+    sort_mode: u32,
+}
+
+/// named layers front to back, a layer's id is its index. sprites with an id past the end
+/// are drawn on the last layer
+#[derive(Clone, Debug, PartialEq)]
+struct RenderLayers(Vec<RenderLayer>);
+impl RenderLayers {
+    fn new() -> Self {
+        let mut layers = Self(vec![]);
+        layers.add("ui", SortMode::ZIndex);
+        layers.add("overhead", SortMode::YSort);
+        layers.add("characters", SortMode::YSort);
+        layers.add("objects", SortMode::YSort);
+        layers.add("crops", SortMode::YSort);
+        layers.add("ground", SortMode::Insertion);
+        layers
+    }
+
+    /// behind every layer added so far, a name that's taken only has its sort mode changed
+    fn add(&mut self, name: &str, sort: SortMode) -> u32 {
+        if let Some(id) = self.get(name) {
+            self.0[id as usize].sort = sort;
+            return id;
+        }
+        self.0.push(RenderLayer {
+            name: name.to_string(),
+            sort,
+        });
+        self.0.len() as u32 - 1
+    }
+
+    fn get(&self, name: &str) -> Option<u32> {
+        self.0
+            .iter()
+            .position(|layer| layer.name == name)
+            .map(|id| id as u32)
+    }
+
+    fn clamp(&self, id: u32) -> u32 {
+        id.min((self.0.len() as u32).saturating_sub(1))
+    }
+
+    fn data(&self, id: u32) -> LayerData {
+        let id = self.clamp(id);
+        let band = 1.0 / self.0.len() as f32;
+        LayerData {
+            depth_base: id as f32 * band,
+            depth_range: band * 0.9,
+            tile_depth: (id as f32 + 0.95) * band,
+            sort_mode: self.0[id as usize].sort as u32,
+        }
+    }
+}
+
+// ------------------------------------------------------------------ //
+/// palette names in the order of their rows in palettes.png, the first name is row 1 since
+/// row 0 holds the colours being swapped
+#[derive(Clone, Debug)]
+struct Palettes(Vec<String>);
+impl Palettes {
+    fn new() -> Self {
+        Self(vec![])
+    }
+
+    fn get(&self, name: &str) -> Option<u32> {
+        self.0
+            .iter()
+            .position(|palette| palette == name)
+            .map(|row| row as u32 + 1)
+    }
+}
+
+#[derive(Debug)]
+enum PrefabError {
+    Io(std::path::PathBuf, std::io::Error),
+    Parse(std::path::PathBuf, serde_json::Error),
+    UnknownName(String),
+    InvalidLayer { name: String, layer: String },
+    InvalidPalette { name: String, palette: String },
+    MissingSprite { name: String, sprite: String },
+}
+impl std::fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrefabError::Io(path, err) => {
+                write!(f, "could not read prefab file {}: {err}", path.display())
+            }
+            PrefabError::Parse(path, err) => {
+                write!(f, "could not parse prefab file {}: {err}", path.display())
+            }
+            PrefabError::UnknownName(name) => write!(f, "no prefab named {name:?}"),
+            PrefabError::InvalidLayer { name, layer } => {
+                write!(
+                    f,
+                    "prefab {name:?} is on layer {layer:?}, which isn't registered"
+                )
+            }
+            PrefabError::InvalidPalette { name, palette } => {
+                write!(
+                    f,
+                    "prefab {name:?} uses palette {palette:?}, which isn't in palettes.json"
+                )
+            }
+            PrefabError::MissingSprite { name, sprite } => write!(
+                f,
+                "prefab {name:?} uses sprite {sprite:?}, which isn't in src/res/sprites"
+            ),
+        }
+    }
+}
+impl std::error::Error for PrefabError {}
+
+/// atlas regions by name, loaded from a json file so new sprites don't need a recompile
+struct Prefab {
+    map: std::collections::HashMap<String, TextureData>,
+    // the layout of the atlas the prefabs point into, a repack has to match it
+    regions: std::collections::HashMap<String, AtlasRegion>,
+}
+impl Prefab {
+    /// every packed sprite becomes a prefab, entries in the json file add the animation,
+    /// origin and layer data on top of the packed region they name
+    fn load<P: AsRef<std::path::Path>>(
+        path: P,
+        atlas: &PackedAtlas,
+        layers: &RenderLayers,
+        palettes: &Palettes,
+    ) -> Result<Self, PrefabError> {
+        let path = path.as_ref();
+        let file = std::fs::read_to_string(path)
+            .map_err(|err| PrefabError::Io(path.to_path_buf(), err))?;
+        let definitions: std::collections::HashMap<String, PrefabDefinition> =
+            serde_json::from_str(&file)
+                .map_err(|err| PrefabError::Parse(path.to_path_buf(), err))?;
+
+        let mut map = std::collections::HashMap::new();
+        for (name, definition) in definitions {
+            let sprite = definition.sprite.as_ref().unwrap_or(&name);
+            let region = atlas
+                .regions
+                .get(sprite)
+                .ok_or_else(|| PrefabError::MissingSprite {
+                    name: name.clone(),
+                    sprite: sprite.clone(),
+                })?;
+            // a packed strip holds every frame side by side
+            let size = definition
+                .size
+                .unwrap_or((region.size.0 / definition.frame_num.max(1), region.size.1));
+            let layer = Self::layer_id(&name, &definition.layer, layers)?;
+            let palette = match &definition.palette {
+                Some(palette) => {
+                    palettes
+                        .get(palette)
+                        .ok_or_else(|| PrefabError::InvalidPalette {
+                            name: name.clone(),
+                            palette: palette.clone(),
+                        })?
+                }
+                None => 0,
+            };
+            map.insert(
+                name,
+                TextureData {
+                    top_left_tex_pos: region.top_left_tex_pos,
+                    size,
+                    origin_offset: definition.origin_offset.unwrap_or(size.1),
+                    frame_num: definition.frame_num,
+                    frame_interval: definition.frame_interval,
+                    looping: definition.looping,
+                    layer,
+                    tint: definition.tint,
+                    opacity: definition.opacity,
+                    flip_x: definition.flip_x,
+                    flip_y: definition.flip_y,
+                    rotation: definition.rotation % 4,
+                    silhouette: definition.silhouette,
+                    sway: definition.sway,
+                    palette,
+                    material: definition.material,
+                },
+            );
+        }
+        for (name, region) in atlas.regions.iter() {
+            if map.contains_key(name) {
+                continue;
+            }
+            let layer = Self::layer_id(name, &PrefabDefinition::default_layer(), layers)?;
+            map.insert(
+                name.clone(),
+                TextureData {
+                    top_left_tex_pos: region.top_left_tex_pos,
+                    size: region.size,
+                    origin_offset: region.size.1,
+                    frame_num: 1,
+                    frame_interval: 0.0,
+                    looping: false,
+                    layer,
+                    tint: PrefabDefinition::default_tint(),
+                    opacity: 1.0,
+                    flip_x: false,
+                    flip_y: false,
+                    rotation: 0,
+                    silhouette: None,
+                    sway: 0.0,
+                    palette: 0,
+                    material: TileMaterial::Plain,
+                },
+            );
+        }
+        Ok(Self {
+            map,
+            regions: atlas.regions.clone(),
+        })
+    }
+
+    fn layer_id(name: &str, layer: &str, layers: &RenderLayers) -> Result<u32, PrefabError> {
+        layers.get(layer).ok_or_else(|| PrefabError::InvalidLayer {
+            name: name.to_string(),
+            layer: layer.to_string(),
+        })
+    }
+
+    fn get(&self, name: &str) -> Result<&TextureData, PrefabError> {
+        self.map
+            .get(name)
+            .ok_or_else(|| PrefabError::UnknownName(name.to_string()))
+    }
+
+    /// registers the prefab's atlas region as a tile, only its top left corner is used since
+    /// every tile is tile_size wide. frames and the frame interval carry over
+    fn tile(&self, name: &str, tilemap: &mut Tilemap) -> Result<u32, PrefabError> {
+        let tex_data = self.get(name)?;
+        Ok(tilemap.add_tile(TileDef {
+            top_left_tex_pos: tex_data.top_left_tex_pos,
+            frame_count: tex_data.frame_num,
+            frame_interval: tex_data.frame_interval,
+            material: tex_data.material,
+        }))
+    }
+
+    /// the prefab's frames and layer for an emitter, its size is the size of one frame
+    fn particle_frames(&self, name: &str) -> Result<ParticleFrames, PrefabError> {
+        let tex_data = self.get(name)?;
+        Ok(ParticleFrames {
+            top_left_tex_pos: tex_data.top_left_tex_pos,
+            frame_size: tex_data.size,
+            frame_count: tex_data.frame_num,
+            layer: tex_data.layer,
+        })
+    }
+
+    fn gen(&self, name: &str, position: (f32, f32)) -> Result<Sprite, PrefabError> {
+        let tex_data = self.get(name)?;
+        Ok(Sprite {
+            top_left_position_x: position.0,
+            top_left_position_y: position.1,
+            top_left_tex_coords_x: tex_data.top_left_tex_pos.0 as _,
+            top_left_tex_coords_y: tex_data.top_left_tex_pos.1 as _,
+            width: tex_data.size.0 as _,
+            height: tex_data.size.1 as _,
+            layer: tex_data.layer,
+            origin_offset_y: tex_data.origin_offset as _,
+
+            frame_interval: tex_data.frame_interval,
+            frame_num: tex_data.frame_num,
+            looping: if tex_data.looping { 1 } else { 0 },
+            anim_index: 0,
+            order: 0,
+            tint_r: tex_data.tint[0],
+            tint_g: tex_data.tint[1],
+            tint_b: tex_data.tint[2],
+            tint_a: tex_data.tint[3],
+            screen_space: 0,
+            flip_x: tex_data.flip_x as u32,
+            flip_y: tex_data.flip_y as u32,
+            rotation: tex_data.rotation,
+            opacity: tex_data.opacity,
+            z_index: 0,
+            silhouette: tex_data.silhouette.map_or(0, pack_color),
+            sway: tex_data.sway,
+            palette: tex_data.palette,
+            outline: 0,
+        })
+    }
+}
+
+// ------------------------------------------------------------------ //
+#[derive(Debug)]
+enum AtlasError {
+    Io(std::path::PathBuf, std::io::Error),
+    Decode(std::path::PathBuf, image::ImageError),
+    TooLarge {
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+    DuplicateName {
+        name: String,
+        first: std::path::PathBuf,
+        second: std::path::PathBuf,
+    },
+}
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::Io(path, err) => write!(f, "could not read {}: {err}", path.display()),
+            AtlasError::Decode(path, err) => {
+                write!(f, "could not decode {}: {err}", path.display())
+            }
+            AtlasError::TooLarge { width, height, max } => write!(
+                f,
+                "packed atlas is {width}x{height} but the adapter only supports textures up to {max}x{max}"
+            ),
+            AtlasError::DuplicateName {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} and {} would both be packed as {name:?}",
+                first.display(),
+                second.display()
+            ),
+        }
+    }
+}
+impl std::error::Error for AtlasError {}
+
+#[derive(Clone, Debug, PartialEq)]
+struct AtlasRegion {
+    top_left_tex_pos: (u32, u32),
+    size: (u32, u32),
+}
+
+struct PackedAtlas {
+    image: image::RgbaImage,
+    regions: std::collections::HashMap<String, AtlasRegion>,
+}
+
+/// packs every png in `dir` into one atlas using rows of decreasing height, regions are named
+/// after the file up to its first dot so "char_main.strip.png" becomes "char_main"
+fn pack_atlas<P: AsRef<std::path::Path>>(dir: P, max_size: u32) -> Result<PackedAtlas, AtlasError> {
+    let dir = dir.as_ref();
+    let mut images = vec![];
+    // the region name is only the part before the first dot, so two files can end up with it
+    let mut paths: std::collections::HashMap<String, std::path::PathBuf> =
+        std::collections::HashMap::new();
+    for entry in std::fs::read_dir(dir).map_err(|err| AtlasError::Io(dir.to_path_buf(), err))? {
+        let path = entry
+            .map_err(|err| AtlasError::Io(dir.to_path_buf(), err))?
+            .path();
+        if path.extension().map_or(true, |ext| ext != "png") {
+            continue;
+        }
+        let image = image::io::Reader::open(&path)
+            .map_err(|err| AtlasError::Io(path.clone(), err))?
+            .decode()
+            .map_err(|err| AtlasError::Decode(path.clone(), err))?
+            .into_rgba8();
+        let name = path.file_prefix().unwrap().to_string_lossy().to_string();
+        if let Some(first) = paths.insert(name.clone(), path.clone()) {
+            return Err(AtlasError::DuplicateName {
+                name,
+                first,
+                second: path,
+            });
+        }
+        images.push((name, image));
+    }
+    // tallest first keeps the rows tight, ties broken by name so the layout is reproducible
+    images.sort_by(|(name_a, a), (name_b, b)| b.height().cmp(&a.height()).then(name_a.cmp(name_b)));
+
+    let widest = images
+        .iter()
+        .map(|(_, image)| image.width())
+        .max()
+        .unwrap_or(1);
+    let area: u64 = images
+        .iter()
+        .map(|(_, image)| image.width() as u64 * image.height() as u64)
+        .sum();
+    let width = ((area as f64).sqrt().ceil() as u32)
+        .max(widest)
+        .next_power_of_two();
+
+    let mut positions = vec![];
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+    for (_, image) in images.iter() {
+        if x + image.width() > width {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        positions.push((x, y));
+        x += image.width();
+        row_height = row_height.max(image.height());
+    }
+    let height = y + row_height;
+    if width > max_size || height > max_size {
+        return Err(AtlasError::TooLarge {
+            width,
+            height,
+            max: max_size,
+        });
+    }
+
+    let mut atlas = image::RgbaImage::new(width, height.max(1));
+    let mut regions = std::collections::HashMap::new();
+    for ((name, image), (x, y)) in images.into_iter().zip(positions) {
+        image::imageops::replace(&mut atlas, &image, x as i64, y as i64);
+        regions.insert(
+            name,
+            AtlasRegion {
+                top_left_tex_pos: (x, y),
+                size: image.dimensions(),
+            },
+        );
+    }
+    Ok(PackedAtlas {
+        image: atlas,
+        regions,
+    })
+}
+
+// ------------------------------------------------------------------ //
+#[derive(Debug)]
+enum FontError {
+    Io(std::path::PathBuf, std::io::Error),
+    Parse(std::path::PathBuf, serde_json::Error),
+    Sprite(PrefabError),
+}
+impl std::fmt::Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontError::Io(path, err) => {
+                write!(f, "could not read font file {}: {err}", path.display())
+            }
+            FontError::Parse(path, err) => {
+                write!(f, "could not parse font file {}: {err}", path.display())
+            }
+            FontError::Sprite(err) => write!(f, "could not find the font's glyphs: {err}"),
+        }
+    }
+}
+impl std::error::Error for FontError {}
+
+/// a monospaced font laid out as a grid of equally sized cells in a packed sprite, in
+/// character order starting from first_char, glyphs should be white so the text colour can
+/// tint them
+#[derive(serde::Deserialize, Debug)]
+struct BitmapFont {
+    // the packed sprite holding the grid
+    sprite: String,
+    // looked up from sprite by load
+    #[serde(skip)]
+    top_left_tex_pos: (u32, u32),
+    // size of a cell, spacing between glyphs and lines is part of the cell
+    glyph_size: (u32, u32),
+    columns: u32,
+    #[serde(default = "BitmapFont::default_first_char")]
+    first_char: u32,
+    #[serde(default = "BitmapFont::default_glyph_count")]
+    glyph_count: u32,
+}
+impl BitmapFont {
+    fn default_first_char() -> u32 {
+        32
+    }
+
+    // printable ascii
+    fn default_glyph_count() -> u32 {
+        95
+    }
+
+    fn load<P: AsRef<std::path::Path>>(path: P, prefab: &Prefab) -> Result<Self, FontError> {
+        let path = path.as_ref();
+        let file =
+            std::fs::read_to_string(path).map_err(|err| FontError::Io(path.to_path_buf(), err))?;
+        let mut font: Self =
+            serde_json::from_str(&file).map_err(|err| FontError::Parse(path.to_path_buf(), err))?;
+        font.top_left_tex_pos = prefab
+            .get(&font.sprite)
+            .map_err(FontError::Sprite)?
+            .top_left_tex_pos;
+        Ok(font)
+    }
+
+    /// splits the text into lines no wider than max_width, at spaces where possible and in
+    /// the middle of a word when it doesn't fit on a line of its own
+    fn wrap(&self, text: &str, max_width: Option<f32>) -> Vec<String> {
+        let max_chars = max_width.map(|width| ((width / self.glyph_size.0 as f32) as usize).max(1));
+        let mut lines = vec![];
+        for paragraph in text.split('\n') {
+            let Some(max_chars) = max_chars else {
+                lines.push(paragraph.to_string());
+                continue;
+            };
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let line_len = line.chars().count();
+                let word_len = word.chars().count();
+                if line_len > 0 && line_len + 1 + word_len <= max_chars {
+                    line.push(' ');
+                    line.push_str(word);
+                    continue;
+                }
+                if line_len > 0 {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let mut chars: Vec<char> = word.chars().collect();
+                while chars.len() > max_chars {
+                    lines.push(chars.drain(..max_chars).collect());
+                }
+                line = chars.into_iter().collect();
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// one sprite per visible glyph, every glyph of a block sorts by the bottom of the block
+    /// so the text moves between sprites as a whole
+    fn layout(&self, text: &Text) -> Vec<Sprite> {
+        let (glyph_width, glyph_height) = (self.glyph_size.0 as f32, self.glyph_size.1 as f32);
+        let lines = self.wrap(&text.string, text.max_width);
+        let block_width = text.max_width.unwrap_or_else(|| {
+            lines
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0) as f32
+                * glyph_width
+        });
+        let bottom = text.position.1 - lines.len() as f32 * glyph_height;
+
+        let mut sprites = vec![];
+        for (row, line) in lines.iter().enumerate() {
+            let line_width = line.chars().count() as f32 * glyph_width;
+            let x = text.position.0
+                + match text.align {
+                    TextAlign::Left => 0.0,
+                    TextAlign::Center => ((block_width - line_width) / 2.0).floor(),
+                    TextAlign::Right => block_width - line_width,
+                };
+            let y = text.position.1 - row as f32 * glyph_height;
+            for (column, char) in line.chars().enumerate() {
+                if char == ' ' {
+                    continue;
+                }
+                // anything the font doesn't have shows up as a question mark
+                let mut index = (char as u32).wrapping_sub(self.first_char);
+                if index >= self.glyph_count {
+                    index = ('?' as u32).wrapping_sub(self.first_char);
+                }
+                sprites.push(Sprite {
+                    top_left_position_x: x + column as f32 * glyph_width,
+                    top_left_position_y: y,
+                    top_left_tex_coords_x: (self.top_left_tex_pos.0
+                        + index % self.columns * self.glyph_size.0)
+                        as _,
+                    top_left_tex_coords_y: (self.top_left_tex_pos.1
+                        + index / self.columns * self.glyph_size.1)
+                        as _,
+                    width: glyph_width,
+                    height: glyph_height,
+                    layer: text.layer,
+                    origin_offset_y: y - bottom,
+                    tint_r: text.color[0],
+                    tint_g: text.color[1],
+                    tint_b: text.color[2],
+                    tint_a: text.color[3],
+                    screen_space: if text.screen_space { 1 } else { 0 },
+                    frame_num: 1,
+                    ..Sprite::empty()
+                });
+            }
+        }
+        sprites
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// a string on screen, laid out into glyph sprites by BitmapFont::layout every tick
+#[derive(Clone, Debug)]
+struct Text {
+    string: String,
+    // top left corner of the block
+    position: (f32, f32),
+    color: [f32; 4],
+    align: TextAlign,
+    // longer lines are wrapped, alignment is within this width when it's set
+    max_width: Option<f32>,
+    // 0 is the ui layer in RenderLayers::new
+    layer: u32,
+    // position is in scene pixels from the centre of the screen and the camera is ignored
+    screen_space: bool,
+}
+impl Text {
+    fn new(string: &str, position: (f32, f32)) -> Self {
+        Self {
+            string: string.to_string(),
+            position,
+            color: [1.0, 1.0, 1.0, 1.0],
+            align: TextAlign::Left,
+            max_width: None,
+            layer: 0,
+            screen_space: false,
+        }
+    }
+}
+
+// ------------------------------------------------------------------ //
+const CHUNK_SIZE: usize = 16;
+
+/// a cell as the tile shader sees it, ids are resolved to atlas positions on upload
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct TileInstance {
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    // 0 for cells without a tile
+    visible: u32,
+    material: u32,
+    frame_count: u32,
+    frame_interval: f32,
+    _padding: [u32; 2],
+}
+
+/// how a tile is shaded on top of the atlas colour, see shade in shader.wgsl
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TileMaterial {
+    #[default]
+    Plain,
+    // scrolls and wobbles inside its frame, sprites at the edge are reflected in it
+    Water,
+}
+
+/// an entry in the tileset, animated ones are all on the same frame since the frame comes
+/// from utime alone
+#[derive(Clone, Debug)]
+struct TileDef {
+    top_left_tex_pos: (u32, u32),
+    // side by side in the atlas, tile_size apart
+    frame_count: u32,
+    frame_interval: f32,
+    material: TileMaterial,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct ChunkData {
+    // bottom left corner of the chunk in world units
+    origin_x: f32,
+    origin_y: f32,
+    depth: f32,
+    tile_size: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Chunk {
+    // tile ids row by row starting from the bottom, 0 is an empty cell
+    tiles: Vec<u32>,
+    // edited since the renderer last uploaded it
+    dirty: bool,
+}
+
+#[derive(Debug)]
+enum TilemapError {
+    UnknownTile { id: u32, tile_count: u32 },
+}
+impl std::fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TilemapError::UnknownTile { id, tile_count } => write!(
+                f,
+                "no tile with id {id}, the tileset only has {tile_count} tiles"
+            ),
+        }
+    }
+}
+impl std::error::Error for TilemapError {}
+
+/// ground tiles on the same layers as sprites, kept in square chunks that are uploaded
+/// and drawn whole instead of going through the sprite sort
+#[derive(Clone, Debug)]
+struct Tilemap {
+    tile_size: u32,
+    // tile id n is tileset[n - 1]
+    tileset: Vec<TileDef>,
+    // keyed by layer and chunk position, tile (x, y) covers x * tile_size to (x + 1) * tile_size
+    chunks: std::collections::HashMap<(u32, i32, i32), Chunk>,
+}
+impl Tilemap {
+    fn new(tile_size: u32) -> Self {
+        Self {
+            tile_size,
+            tileset: vec![],
+            chunks: std::collections::HashMap::new(),
+        }
+    }
+
+    /// returns the id to place with set_tile
+    fn add_tile(&mut self, tile: TileDef) -> u32 {
+        self.tileset.push(tile);
+        self.tileset.len() as u32
+    }
+
+    fn split(position: (i32, i32)) -> ((i32, i32), usize) {
+        let size = CHUNK_SIZE as i32;
+        let chunk = (position.0.div_euclid(size), position.1.div_euclid(size));
+        let cell = position.1.rem_euclid(size) * size + position.0.rem_euclid(size);
+        (chunk, cell as usize)
+    }
+
+    fn set_tile(&mut self, layer: u32, position: (i32, i32), id: u32) -> Result<(), TilemapError> {
+        if id as usize > self.tileset.len() {
+            return Err(TilemapError::UnknownTile {
+                id,
+                tile_count: self.tileset.len() as u32,
             });
         }
-        self.queue.write_buffer(
-            &self.storage_buffer,
-            0,
-            bytemuck::cast_slice(&self.sorted_sprites),
-        );
+        let (chunk, cell) = Self::split(position);
+        let chunk = self
+            .chunks
+            .entry((layer, chunk.0, chunk.1))
+            .or_insert_with(|| Chunk {
+                tiles: vec![0; CHUNK_SIZE * CHUNK_SIZE],
+                dirty: true,
+            });
+        if chunk.tiles[cell] != id {
+            chunk.tiles[cell] = id;
+            chunk.dirty = true;
+        }
+        Ok(())
     }
 
-    fn render(&mut self) {
-        let canvas = self.surface.get_current_texture().unwrap();
-        let canvas_view = canvas
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let depth_view = self
-            .depth_stencil_texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &canvas_view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::RED),
-                    store: false,
-                },
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &depth_view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: false,
-                }),
-                stencil_ops: None,
-            }),
+    fn get_tile(&self, layer: u32, position: (i32, i32)) -> u32 {
+        let (chunk, cell) = Self::split(position);
+        self.chunks
+            .get(&(layer, chunk.0, chunk.1))
+            .map_or(0, |chunk| chunk.tiles[cell])
+    }
+
+    /// sets every tile from `min` up to and including `max`
+    fn fill(
+        &mut self,
+        layer: u32,
+        min: (i32, i32),
+        max: (i32, i32),
+        id: u32,
+    ) -> Result<(), TilemapError> {
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                self.set_tile(layer, (x, y), id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ------------------------------------------------------------------ //
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct DebugVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+const DEBUG_BOUNDS: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+const DEBUG_ORIGIN: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+const DEBUG_GRID: [f32; 4] = [1.0, 1.0, 1.0, 0.25];
+
+/// immediate mode lines in world units over the finished scene, whatever was added this frame
+/// is drawn once and cleared. everything is ignored while it's disabled
+#[derive(Clone, Debug)]
+struct DebugDraw {
+    enabled: bool,
+    vertices: Vec<DebugVertex>,
+}
+impl DebugDraw {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            vertices: vec![],
+        }
+    }
+
+    /// points name the pixel whose top left corner they sit on, like sprite positions do
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), color: [f32; 4]) {
+        if !self.enabled {
+            return;
+        }
+        self.vertices.push(DebugVertex {
+            position: [from.0, from.1],
+            color,
         });
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.bind_group, &[]);
-        // todo, change this so it always draws everything
-        render_pass.draw(0..6 * self.sorted_sprites.len() as u32, 0..1);
-        drop(render_pass);
-        self.queue.submit(Some(encoder.finish()));
-        canvas.present();
+        self.vertices.push(DebugVertex {
+            position: [to.0, to.1],
+            color,
+        });
+    }
+
+    /// the outline sits on the outermost pixels of the area, for collision boxes and the like
+    fn rect(&mut self, top_left: (f32, f32), size: (f32, f32), color: [f32; 4]) {
+        let right = top_left.0 + size.0 - 1.0;
+        let bottom = top_left.1 - size.1 + 1.0;
+        self.line(top_left, (right, top_left.1), color);
+        self.line((right, top_left.1), (right, bottom), color);
+        self.line((right, bottom), (top_left.0, bottom), color);
+        self.line((top_left.0, bottom), top_left, color);
+    }
+
+    /// a small cross
+    fn point(&mut self, position: (f32, f32), color: [f32; 4]) {
+        self.line(
+            (position.0 - 2.0, position.1),
+            (position.0 + 3.0, position.1),
+            color,
+        );
+        self.line(
+            (position.0, position.1 + 2.0),
+            (position.0, position.1 - 3.0),
+            color,
+        );
+    }
+
+    /// the quad's bounds and the line it's y-sorted by, see PixelRenderer::depth_order
+    fn sprite(&mut self, sprite: &Sprite) {
+        // screen space sprites don't move with the camera and are drawn over everything anyway
+        if !self.enabled || sprite.screen_space != 0 {
+            return;
+        }
+        let (width, height) = sprite.size();
+        let top_left = (sprite.top_left_position_x, sprite.top_left_position_y);
+        self.rect(top_left, (width, height), DEBUG_BOUNDS);
+        let origin_y = sprite.top_left_position_y - sprite.origin_offset_y;
+        self.line(
+            (top_left.0, origin_y),
+            (top_left.0 + width, origin_y),
+            DEBUG_ORIGIN,
+        );
+        self.point((top_left.0 + (width / 2.0).floor(), origin_y), DEBUG_ORIGIN);
+    }
+
+    /// cell edges of every chunk that has been created
+    fn tile_grid(&mut self, tilemap: &Tilemap) {
+        if !self.enabled {
+            return;
+        }
+        let tile_size = tilemap.tile_size as f32;
+        let extent = tile_size * CHUNK_SIZE as f32;
+        for (_, chunk_x, chunk_y) in tilemap.chunks.keys() {
+            let left = *chunk_x as f32 * extent;
+            let bottom = *chunk_y as f32 * extent;
+            // each line lands on the left column or the top row of the cells next to it
+            for cell in 0..=CHUNK_SIZE {
+                let offset = cell as f32 * tile_size;
+                self.line(
+                    (left + offset, bottom + extent),
+                    (left + offset, bottom),
+                    DEBUG_GRID,
+                );
+                self.line(
+                    (left, bottom + offset),
+                    (left + extent, bottom + offset),
+                    DEBUG_GRID,
+                );
+            }
+        }
     }
 }
 
 // ------------------------------------------------------------------ //
-struct Prefab {
-    map: std::collections::HashMap<String, TextureData>,
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OcclusionMode {
+    // covered parts of sprites with a silhouette colour are drawn in that colour
+    Silhouette,
+    // whatever is in front of a sprite with a silhouette colour is faded out instead
+    FadeOccluders,
 }
-impl Prefab {
+
+#[derive(Clone, Debug)]
+struct Occlusion {
+    mode: OcclusionMode,
+    // opacity multiplied into faded occluders
+    fade_opacity: f32,
+}
+impl Occlusion {
     fn new() -> Self {
-        let mut map: std::collections::HashMap<String, TextureData> =
-            std::collections::HashMap::new();
-        map.insert(
-            "char_main".to_string(),
-            TextureData {
-                top_left_tex_pos: (0, 0),
-                size: (32, 32),
-                origin_offset: 24,
+        Self {
+            mode: OcclusionMode::Silhouette,
+            fade_opacity: 0.4,
+        }
+    }
+
+    /// fades every sprite that is drawn after one with a silhouette and overlaps it. faded
+    /// sprites go through the translucent pass and stop writing depth, which also keeps the
+    /// silhouette from showing through them
+    fn fade_occluders(&self, sprites: &mut [Sprite], layers: &RenderLayers) {
+        if self.mode != OcclusionMode::FadeOccluders {
+            return;
+        }
+        let covered: Vec<Sprite> = sprites
+            .iter()
+            .filter(|sprite| sprite.silhouette != 0 && sprite.screen_space == 0)
+            .copied()
+            .collect();
+        for sprite in sprites.iter_mut() {
+            if sprite.screen_space != 0 || sprite.silhouette != 0 {
+                continue;
+            }
+            let occludes = covered.iter().any(|behind| {
+                Self::overlaps(sprite, behind)
+                    && PixelRenderer::depth_order(behind, sprite, layers)
+                        == std::cmp::Ordering::Less
+            });
+            if occludes {
+                sprite.opacity *= self.fade_opacity;
+            }
+        }
+    }
+
+    fn overlaps(a: &Sprite, b: &Sprite) -> bool {
+        let (a_width, a_height) = a.size();
+        let (b_width, b_height) = b.size();
+        a.top_left_position_x < b.top_left_position_x + b_width
+            && b.top_left_position_x < a.top_left_position_x + a_width
+            && a.top_left_position_y - a_height < b.top_left_position_y
+            && b.top_left_position_y - b_height < a.top_left_position_y
+    }
+}
+
+// ------------------------------------------------------------------ //
+/// a light as light.wgsl sees it, in world units
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct LightInstance {
+    position_x: f32,
+    position_y: f32,
+    radius: f32,
+    intensity: f32,
+    color_r: f32,
+    color_g: f32,
+    color_b: f32,
+    _padding: u32,
+}
+
+/// a point light, falls off to nothing at `radius`
+#[derive(Clone, Debug)]
+struct Light {
+    // follows the origin of this entity's sprite when set, otherwise offset is the world position
+    entity: Option<usize>,
+    offset: (f32, f32),
+    radius: f32,
+    color: [f32; 3],
+    intensity: f32,
+}
+impl Light {
+    /// `sprite` is the one belonging to `entity`
+    fn to_instance(&self, sprite: Option<&Sprite>) -> LightInstance {
+        let position = match sprite {
+            Some(sprite) => (
+                sprite.top_left_position_x + sprite.width / 2.0 + self.offset.0,
+                sprite.top_left_position_y - sprite.origin_offset_y + self.offset.1,
+            ),
+            None => self.offset,
+        };
+        LightInstance {
+            position_x: position.0,
+            position_y: position.1,
+            radius: self.radius,
+            intensity: self.intensity,
+            color_r: self.color[0],
+            color_g: self.color[1],
+            color_b: self.color[2],
+            _padding: 0,
+        }
+    }
+}
+
+/// in game time of day, drives the ambient light
+#[derive(Clone, Debug)]
+struct GameClock {
+    // 0 to 24
+    hours: f32,
+    // in game hours that pass every real second
+    speed: f32,
+}
+impl GameClock {
+    // hour and ambient colour, blended linearly in between and wrapping around midnight
+    const AMBIENT: [(f32, [f32; 3]); 6] = [
+        (5.0, [0.15, 0.18, 0.35]),
+        (7.0, [1.0, 0.8, 0.7]),
+        (9.0, [1.0, 1.0, 1.0]),
+        (17.0, [1.0, 1.0, 1.0]),
+        (19.0, [1.0, 0.65, 0.5]),
+        (21.0, [0.15, 0.18, 0.35]),
+    ];
+
+    fn new(hours: f32) -> Self {
+        Self {
+            hours,
+            speed: 1.0 / 60.0,
+        }
+    }
+
+    fn advance(&mut self, delta_time: f32) {
+        self.hours = (self.hours + delta_time * self.speed).rem_euclid(24.0);
+    }
+
+    fn ambient(&self) -> [f32; 3] {
+        let keys = Self::AMBIENT;
+        let next = keys
+            .iter()
+            .position(|(hour, _)| *hour > self.hours)
+            .unwrap_or(0);
+        let (from_hour, from) = keys[(next + keys.len() - 1) % keys.len()];
+        let (to_hour, to) = keys[next];
+        let span = (to_hour - from_hour).rem_euclid(24.0);
+        let t = (self.hours - from_hour).rem_euclid(24.0) / span;
+        [
+            from[0] + (to[0] - from[0]) * t,
+            from[1] + (to[1] - from[1]) * t,
+            from[2] + (to[2] - from[2]) * t,
+        ]
+    }
+}
+
+// ------------------------------------------------------------------ //
+/// slots in the particle ring, emitting past this overwrites the oldest ones
+const PARTICLE_CAPACITY: usize = 4096;
+
+/// a particle as particle.wgsl and vs_particle see it, world units and seconds
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct Particle {
+    position_x: f32,
+    position_y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    // negative until it's born, dead once it reaches lifetime
+    age: f32,
+    lifetime: f32,
+    gravity: f32,
+    layer: u32,
+    color_start: [f32; 4],
+    color_end: [f32; 4],
+    size_start: f32,
+    size_end: f32,
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    frame_width: f32,
+    frame_height: f32,
+    frame_count: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct ParticleStep {
+    delta_time: f32,
+    _padding: [u32; 3],
+}
+
+/// frame_count frames side by side in the atlas, played once over a particle's life. see
+/// Prefab::particle_frames
+#[derive(Clone, Copy, Debug)]
+struct ParticleFrames {
+    top_left_tex_pos: (u32, u32),
+    frame_size: (u32, u32),
+    frame_count: u32,
+    layer: u32,
+}
+
+/// spawns particles at a steady rate, everything but the spawn position is left to the gpu
+#[derive(Clone, Debug)]
+struct Emitter {
+    // follows the origin of this entity's sprite when set, otherwise offset is the world position
+    entity: Option<usize>,
+    offset: (f32, f32),
+    // particles start anywhere in a box this size centred on the spawn point
+    spread: (f32, f32),
+    // particles a second, 0 stops it without cutting off the ones already out
+    rate: f32,
+    // emitters that are otherwise the same spread their particles differently
+    seed: u32,
+    // every particle picks its own between the min and the max
+    lifetime: (f32, f32),
+    velocity_min: (f32, f32),
+    velocity_max: (f32, f32),
+    // world units a second squared, pulling down
+    gravity: f32,
+    // the tint and the frame scale go from start to end over a particle's life
+    color_start: [f32; 4],
+    color_end: [f32; 4],
+    size_start: f32,
+    size_end: f32,
+    frames: ParticleFrames,
+}
+impl Emitter {
+    /// appends the particles born in `from..to` aged to `to`, `sprite` is the one belonging to
+    /// `entity`. births fall on multiples of 1 / rate so nothing has to be written back
+    fn emit(&self, sprite: Option<&Sprite>, from: f32, to: f32, particles: &mut Vec<Particle>) {
+        if self.rate <= 0.0 || to <= from {
+            return;
+        }
+        let position = match sprite {
+            Some(sprite) => (
+                sprite.top_left_position_x + sprite.width / 2.0 + self.offset.0,
+                sprite.top_left_position_y - sprite.origin_offset_y + self.offset.1,
+            ),
+            None => self.offset,
+        };
+        let last = (to * self.rate).floor() as u64;
+        // after a long stall only as many as the ring holds
+        let first = ((from * self.rate).floor() as u64 + 1)
+            .max((last + 1).saturating_sub(PARTICLE_CAPACITY as u64));
+        for number in first..=last {
+            let between =
+                |min: f32, max: f32, stream: u32| min + (max - min) * self.random(number, stream);
+            particles.push(Particle {
+                position_x: position.0 + between(-0.5, 0.5, 3) * self.spread.0,
+                position_y: position.1 + between(-0.5, 0.5, 4) * self.spread.1,
+                velocity_x: between(self.velocity_min.0, self.velocity_max.0, 0),
+                velocity_y: between(self.velocity_min.1, self.velocity_max.1, 1),
+                age: to - number as f32 / self.rate,
+                lifetime: between(self.lifetime.0, self.lifetime.1, 2),
+                gravity: self.gravity,
+                layer: self.frames.layer,
+                color_start: self.color_start,
+                color_end: self.color_end,
+                size_start: self.size_start,
+                size_end: self.size_end,
+                top_left_tex_coords_x: self.frames.top_left_tex_pos.0 as f32,
+                top_left_tex_coords_y: self.frames.top_left_tex_pos.1 as f32,
+                frame_width: self.frames.frame_size.0 as f32,
+                frame_height: self.frames.frame_size.1 as f32,
+                frame_count: self.frames.frame_count.max(1),
+                _padding: 0,
+            });
+        }
+    }
+
+    /// 0 to 1, always the same for a given particle and stream
+    fn random(&self, number: u64, stream: u32) -> f32 {
+        let mut x = self.seed
+            ^ (number as u32).wrapping_mul(0x9e37_79b9)
+            ^ stream.wrapping_mul(0x85eb_ca6b);
+        // lowbias32
+        x ^= x >> 16;
+        x = x.wrapping_mul(0x7feb_352d);
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x846c_a68b);
+        x ^= x >> 16;
+        x as f32 / u32::MAX as f32
+    }
+}
+
+// ------------------------------------------------------------------ //
+/// how much weather the adapter is trusted with, fewer particles and flatter fog further down
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WeatherQuality {
+    Full,
+    Reduced,
+    // software adapters
+    Minimal,
+}
+impl WeatherQuality {
+    fn for_adapter(info: &wgpu::AdapterInfo) -> Self {
+        match info.device_type {
+            wgpu::DeviceType::Cpu => WeatherQuality::Minimal,
+            wgpu::DeviceType::IntegratedGpu => WeatherQuality::Reduced,
+            _ => WeatherQuality::Full,
+        }
+    }
+
+    /// multiplies the rate of every weather emitter
+    fn particle_scale(self) -> f32 {
+        match self {
+            WeatherQuality::Full => 1.0,
+            WeatherQuality::Reduced => 0.5,
+            WeatherQuality::Minimal => 0.25,
+        }
+    }
+
+    fn fog_octaves(self) -> u32 {
+        match self {
+            WeatherQuality::Full => 3,
+            WeatherQuality::Reduced => 2,
+            WeatherQuality::Minimal => 0,
+        }
+    }
+}
+
+/// the prefabs the weather particles are drawn with, their layers included
+#[derive(Clone, Copy, Debug)]
+struct WeatherFrames {
+    rain: ParticleFrames,
+    // where a raindrop lands
+    splash: ParticleFrames,
+    snow: ParticleFrames,
+}
+impl WeatherFrames {
+    fn new(prefab: &Prefab) -> Result<Self, PrefabError> {
+        Ok(Self {
+            rain: prefab.particle_frames("rain")?,
+            splash: prefab.particle_frames("splash")?,
+            snow: prefab.particle_frames("snow")?,
+        })
+    }
+}
+
+/// what the sky is doing, gameplay sets the amounts and the rest follows from them
+#[derive(Clone, Debug)]
+struct Weather {
+    // 0 to 1
+    rain: f32,
+    snow: f32,
+    fog: f32,
+    // -1 to 1, positive blows to the right. sways flagged sprites and pushes rain, snow and fog
+    wind: f32,
+    quality: WeatherQuality,
+    frames: WeatherFrames,
+}
+impl Weather {
+    fn new(quality: WeatherQuality, frames: WeatherFrames) -> Self {
+        Self {
+            rain: 0.0,
+            snow: 0.0,
+            fog: 0.0,
+            wind: 0.0,
+            quality,
+            frames,
+        }
+    }
+
+    /// clear, rain, snow, fog and back to clear
+    fn cycle(&mut self) {
+        let (rain, snow, fog, wind) = if self.rain > 0.0 {
+            (0.0, 0.8, 0.0, 0.3)
+        } else if self.snow > 0.0 {
+            (0.0, 0.0, 0.7, 0.2)
+        } else if self.fog > 0.0 {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (1.0, 0.0, 0.0, 0.6)
+        };
+        self.rain = rain;
+        self.snow = snow;
+        self.fog = fog;
+        self.wind = wind;
+    }
+
+    /// rain and snow fall in from above the view and land somewhere inside it, rebuilt every
+    /// frame around the camera
+    fn emitters(&self, camera: &Camera, viewport: &Viewport) -> Vec<Emitter> {
+        let half_width = viewport.target_width / 2.0 / camera.zoom;
+        let half_height = viewport.target_height / 2.0 / camera.zoom;
+        let scale = self.quality.particle_scale();
+        let mut emitters = vec![];
+        let falling = |frames: ParticleFrames, speed: f32, drift: f32, seed: u32, rate: f32| {
+            // a margin on the side the wind blows from so the view fills in evenly
+            let width = half_width * 2.0 + drift.abs() * 2.0;
+            let height = half_height * 2.0 + 8.0;
+            Emitter {
+                entity: None,
+                offset: (
+                    camera.position.0 - drift,
+                    camera.position.1 + half_height + 4.0,
+                ),
+                spread: (width, 0.0),
+                rate: rate * width * scale,
+                seed,
+                lifetime: (0.25 * height / speed, height / speed),
+                velocity_min: (drift * speed / height, -speed * 1.1),
+                velocity_max: (drift * speed / height, -speed * 0.9),
+                gravity: 0.0,
+                color_start: [1.0, 1.0, 1.0, 1.0],
+                color_end: [1.0, 1.0, 1.0, 1.0],
+                size_start: 1.0,
+                size_end: 1.0,
+                frames,
+            }
+        };
+        if self.rain > 0.0 {
+            emitters.push(Emitter {
+                color_start: [0.75, 0.8, 0.95, 0.6],
+                color_end: [0.75, 0.8, 0.95, 0.6],
+                ..falling(
+                    self.frames.rain,
+                    220.0,
+                    self.wind * 40.0,
+                    2,
+                    self.rain * 1.2,
+                )
+            });
+            // landing spots are picked on their own, there's too many drops to follow each
+            if self.quality != WeatherQuality::Minimal {
+                emitters.push(Emitter {
+                    entity: None,
+                    offset: camera.position,
+                    spread: (half_width * 2.0, half_height * 2.0),
+                    rate: self.rain * half_width * half_height * 0.002 * scale,
+                    seed: 3,
+                    lifetime: (0.2, 0.3),
+                    velocity_min: (0.0, 0.0),
+                    velocity_max: (0.0, 0.0),
+                    gravity: 0.0,
+                    color_start: [0.8, 0.85, 1.0, 0.8],
+                    color_end: [0.8, 0.85, 1.0, 0.2],
+                    size_start: 1.0,
+                    size_end: 1.0,
+                    frames: self.frames.splash,
+                });
+            }
+        }
+        if self.snow > 0.0 {
+            let mut snow = falling(self.frames.snow, 22.0, self.wind * 60.0, 4, self.snow * 0.3);
+            snow.velocity_min.0 -= 4.0;
+            snow.velocity_max.0 += 4.0;
+            emitters.push(Emitter {
+                color_start: [1.0, 1.0, 1.0, 0.95],
+                color_end: [1.0, 1.0, 1.0, 0.5],
+                ..snow
+            });
+        }
+        emitters
+    }
+}
+
+// ------------------------------------------------------------------ //
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct PostData {
+    color_grade: f32,
+    vignette: f32,
+    fade: f32,
+    desaturate: f32,
+    fog: f32,
+    fog_octaves: u32,
+    fog_origin_x: f32,
+    fog_origin_y: f32,
+    fog_zoom: f32,
+    _padding: [u32; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PostEffectKind {
+    // through the lut loaded with PixelRenderer::load_lut
+    ColorGrade,
+    Vignette,
+    // to black, for sleeping and map transitions
+    Fade,
+    Desaturate,
+    // amount follows Weather::fog
+    Fog,
+}
+impl PostEffectKind {
+    // in the order of the discriminants, the renderer keeps one pipeline for each
+    const ALL: [PostEffectKind; 5] = [
+        PostEffectKind::ColorGrade,
+        PostEffectKind::Vignette,
+        PostEffectKind::Fade,
+        PostEffectKind::Desaturate,
+        PostEffectKind::Fog,
+    ];
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            PostEffectKind::ColorGrade => "fs_color_grade",
+            PostEffectKind::Vignette => "fs_vignette",
+            PostEffectKind::Fade => "fs_fade",
+            PostEffectKind::Desaturate => "fs_desaturate",
+            PostEffectKind::Fog => "fs_fog",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PostEffect {
+    kind: PostEffectKind,
+    enabled: bool,
+    // 0 is no effect and 1 the full effect
+    amount: f32,
+}
+
+/// fullscreen effects run on the scene in this order, each kind should only show up once
+#[derive(Clone, Debug)]
+struct PostEffects(Vec<PostEffect>);
+impl PostEffects {
+    fn new() -> Self {
+        Self(vec![
+            // first so the grading and the vignette apply to it like the rest of the world
+            PostEffect {
+                kind: PostEffectKind::Fog,
+                enabled: true,
+                amount: 0.0,
             },
-        );
-        map.insert(
-            "char_alt".to_string(),
-            TextureData {
-                top_left_tex_pos: (0, 32),
-                size: (32, 32),
-                origin_offset: 26,
+            PostEffect {
+                kind: PostEffectKind::ColorGrade,
+                enabled: false,
+                amount: 1.0,
             },
-        );
-        map.insert(
-            "bg_tile".to_string(),
-            TextureData {
-                top_left_tex_pos: (0, 64),
-                size: (32, 32),
-                origin_offset: 16,
+            PostEffect {
+                kind: PostEffectKind::Desaturate,
+                enabled: false,
+                amount: 1.0,
             },
-        );
-        map.insert(
-            "spot".to_string(),
-            TextureData {
-                top_left_tex_pos: (0, 96),
-                size: (32, 32),
-                origin_offset: 32,
+            PostEffect {
+                kind: PostEffectKind::Vignette,
+                enabled: true,
+                amount: 0.35,
             },
-        );
+            PostEffect {
+                kind: PostEffectKind::Fade,
+                enabled: false,
+                amount: 0.0,
+            },
+        ])
+    }
 
-        Self { map }
+    fn get_mut(&mut self, kind: PostEffectKind) -> Option<&mut PostEffect> {
+        self.0.iter_mut().find(|effect| effect.kind == kind)
     }
 
-    fn gen(
-        &self,
-        name: &str,
-        position: (f32, f32),
-        layer: usize,
-        frame_num: u32,
-        frame_interval: f32,
-        looping: bool,
-    ) -> Sprite {
-        let tex_data = self.map.get(name).unwrap();
-        Sprite {
-            top_left_position_x: position.0,
-            top_left_position_y: position.1,
-            top_left_tex_coords_x: tex_data.top_left_tex_pos.0 as _,
-            top_left_tex_coords_y: tex_data.top_left_tex_pos.1 as _,
-            width: tex_data.size.0 as _,
-            height: tex_data.size.1 as _,
-            depth_base: match layer {
-                0 => 0.0,
-                1 => 0.25,
-                2 => 0.5,
-                3 => 0.75,
-                _ => panic!(),
-            },
-            origin_offset_y: tex_data.origin_offset as _,
+    fn toggle(&mut self, kind: PostEffectKind) {
+        if let Some(effect) = self.get_mut(kind) {
+            effect.enabled = !effect.enabled;
+        }
+    }
+}
+
+/// a lut that leaves every colour as it is
+fn identity_lut(size: u32) -> image::RgbaImage {
+    image::RgbaImage::from_fn(size * size, size, |x, y| {
+        let scale = |value: u32| (value * 255 / (size - 1)) as u8;
+        image::Rgba([scale(x % size), scale(y), scale(x / size), 255])
+    })
+}
+
+// ------------------------------------------------------------------ //
+/// something the player can use, `entity`'s sprite is outlined while it's the target
+#[derive(Clone, Debug)]
+struct Interactable {
+    entity: usize,
+}
+
+/// outlines what the player is facing, or what's under the mouse once it has moved. an
+/// interactable sprite there wins over the tile beneath it
+#[derive(Clone, Debug)]
+struct Highlight {
+    color: [f32; 4],
+    // how far in front of the player's origin the target is looked for, world units
+    reach: f32,
+    // the way the player last walked
+    facing: (f32, f32),
+    // window pixels, dropped again when the player walks so facing takes back over
+    cursor: Option<(f32, f32)>,
+    // the entity outlined right now
+    target: Option<usize>,
+    // a see through copy of the targeted tile, only its outline shows
+    tile_marker: usize,
+}
+impl Highlight {
+    fn new(tile_marker: usize) -> Self {
+        Self {
+            color: [1.0, 0.95, 0.6, 1.0],
+            reach: 16.0,
+            facing: (0.0, -1.0),
+            cursor: None,
+            target: None,
+            tile_marker,
+        }
+    }
+
+    /// the world position being targeted
+    fn point(&self, player: &Sprite, camera: &Camera, viewport: &Viewport) -> (f32, f32) {
+        match self.cursor {
+            Some(cursor) => camera.screen_to_world(cursor, viewport),
+            None => (
+                player.top_left_position_x + player.width / 2.0 + self.facing.0 * self.reach,
+                player.top_left_position_y - player.origin_offset_y + self.facing.1 * self.reach,
+            ),
+        }
+    }
+
+    fn contains(sprite: &Sprite, point: (f32, f32)) -> bool {
+        let (width, height) = sprite.size();
+        point.0 >= sprite.top_left_position_x
+            && point.0 < sprite.top_left_position_x + width
+            && point.1 <= sprite.top_left_position_y
+            && point.1 > sprite.top_left_position_y - height
+    }
+}
+
+/// moves the outline onto whatever is targeted this frame, the frontmost interactable at the
+/// point or else the tile the marker's layer has there
+fn update_highlight(table: &mut ecs::Table, player: &Sprite) {
+    let camera = table.read_resource::<Camera>().unwrap().clone();
+    let viewport = *table.read_resource::<Viewport>().unwrap();
+    let layers = table.read_resource::<RenderLayers>().unwrap().clone();
+    let highlight = table.read_resource::<Highlight>().unwrap();
+    let point = highlight.point(player, &camera, &viewport);
+    let outline = pack_color(highlight.color);
+
+    if let Some(sprite) = highlight
+        .target
+        .take()
+        .and_then(|entity| table.read::<SpawnedSprite>(entity))
+    {
+        sprite.outline = 0;
+    }
+    highlight.target = table
+        .query_raw::<Interactable>()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|interactable| {
+            table
+                .read::<SpawnedSprite>(interactable.entity)
+                .map(|sprite| (interactable.entity, **sprite))
+        })
+        .filter(|(_, sprite)| Highlight::contains(sprite, point))
+        .max_by(|a, b| PixelRenderer::depth_order(&a.1, &b.1, &layers))
+        .map(|(entity, _)| entity);
+    if let Some(sprite) = highlight
+        .target
+        .and_then(|entity| table.read::<SpawnedSprite>(entity))
+    {
+        sprite.outline = outline;
+    }
 
-            frame_interval,
-            frame_num,
-            looping: if looping { 1 } else { 0 },
-        }
+    let marker = table.read::<SpawnedSprite>(highlight.tile_marker).unwrap();
+    marker.outline = 0;
+    if highlight.target.is_some() {
+        return;
+    }
+    let tilemap = table.read_resource::<Tilemap>().unwrap();
+    let size = tilemap.tile_size as f32;
+    let cell = (
+        (point.0 / size).floor() as i32,
+        (point.1 / size).floor() as i32,
+    );
+    let id = tilemap.get_tile(marker.layer, cell);
+    if id == 0 {
+        return;
     }
+    let tile = &tilemap.tileset[id as usize - 1];
+    marker.top_left_position_x = cell.0 as f32 * size;
+    marker.top_left_position_y = (cell.1 + 1) as f32 * size;
+    marker.top_left_tex_coords_x = tile.top_left_tex_pos.0 as f32;
+    marker.top_left_tex_coords_y = tile.top_left_tex_pos.1 as f32;
+    marker.width = size;
+    marker.height = size;
+    marker.outline = outline;
 }
 
+// ------------------------------------------------------------------ //
 #[derive(Clone, Debug)]
 struct ArrowKeyState {
     up: bool,
@@ -683,12 +4919,142 @@ impl ArrowKeyState {
     }
 }
 
+/// beyond this too little of the world is left on screen to play
+const MAX_ZOOM: f32 = 16.0;
+
+/// what part of the world ends up on screen, world units are pixels of the atlas
+#[derive(Clone, Debug)]
+struct Camera {
+    // world position shown at the centre of the screen
+    position: (f32, f32),
+    zoom: f32,
+    // lowest and highest point the centre is allowed to reach
+    bounds: Option<((f32, f32), (f32, f32))>,
+    // how quickly the camera closes the gap to its target, higher is snappier
+    follow_speed: f32,
+}
+impl Camera {
+    fn new() -> Self {
+        Self {
+            position: (0.0, 0.0),
+            zoom: 1.0,
+            bounds: None,
+            follow_speed: 8.0,
+        }
+    }
+
+    /// kept from one scene pixel per world unit up to MAX_ZOOM
+    fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+    }
+
+    /// eases towards the target, framerate independent
+    fn follow(&mut self, target: (f32, f32), delta_time: f32) {
+        let t = 1.0 - (-self.follow_speed * delta_time).exp();
+        self.position.0 += (target.0 - self.position.0) * t;
+        self.position.1 += (target.1 - self.position.1) * t;
+        self.clamp_to_bounds();
+    }
+
+    fn clamp_to_bounds(&mut self) {
+        if let Some((min, max)) = self.bounds {
+            self.position.0 = self.position.0.clamp(min.0, max.0);
+            self.position.1 = self.position.1.clamp(min.1, max.1);
+        }
+    }
+
+    /// world units to window pixels with the origin at the top left
+    fn world_to_screen(&self, world: (f32, f32), viewport: &Viewport) -> (f32, f32) {
+        let target_x = (world.0 - self.position.0) * self.zoom + viewport.target_width / 2.0;
+        let target_y = viewport.target_height / 2.0 - (world.1 - self.position.1) * self.zoom;
+        (
+            viewport.x + target_x * viewport.width / viewport.target_width,
+            viewport.y + target_y * viewport.height / viewport.target_height,
+        )
+    }
+
+    /// the inverse of world_to_screen, for mouse picking
+    fn screen_to_world(&self, screen: (f32, f32), viewport: &Viewport) -> (f32, f32) {
+        let target_x = (screen.0 - viewport.x) * viewport.target_width / viewport.width;
+        let target_y = (screen.1 - viewport.y) * viewport.target_height / viewport.height;
+        (
+            (target_x - viewport.target_width / 2.0) / self.zoom + self.position.0,
+            (viewport.target_height / 2.0 - target_y) / self.zoom + self.position.1,
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 struct ShowDeltaTime(bool);
 
 #[derive(Clone, Debug)]
 struct PlayerIndex(usize);
 
+/// every entity that was given a sprite through spawn_sprite along with its serial.
+/// query_raw hands out copies without their entities, so this is how the drawn ones are found
+#[derive(Clone, Debug)]
+struct SpriteEntities {
+    entities: Vec<(usize, u64)>,
+    next_serial: u64,
+}
+impl SpriteEntities {
+    fn new() -> Self {
+        Self {
+            entities: vec![],
+            next_serial: 0,
+        }
+    }
+}
+
+/// kept in a module of its own so spawn_sprite is the only way to make a SpawnedSprite
+mod spawned {
+    use super::{Sprite, SpriteEntities};
+
+    /// the component the renderer draws. a bare Sprite given to insert_new is never drawn
+    #[derive(Clone, Debug)]
+    pub(super) struct SpawnedSprite {
+        sprite: Sprite,
+        serial: u64,
+    }
+    impl SpawnedSprite {
+        /// unique to this sprite, unlike the entity id which can be handed out again once the
+        /// entity is removed. the renderer keys anim slots by it
+        pub(super) fn serial(&self) -> u64 {
+            self.serial
+        }
+    }
+    impl std::ops::Deref for SpawnedSprite {
+        type Target = Sprite;
+
+        fn deref(&self) -> &Sprite {
+            &self.sprite
+        }
+    }
+    impl std::ops::DerefMut for SpawnedSprite {
+        fn deref_mut(&mut self) -> &mut Sprite {
+            &mut self.sprite
+        }
+    }
+
+    pub(super) fn spawn_sprite(table: &mut ecs::Table, sprite: Sprite) -> usize {
+        let sprite_entities = table.read_resource::<SpriteEntities>().unwrap();
+        let serial = sprite_entities.next_serial;
+        sprite_entities.next_serial += 1;
+        let entity = table.insert_new(SpawnedSprite { sprite, serial });
+        sprite_entities.entities.push((entity, serial));
+        entity
+    }
+}
+use spawned::{spawn_sprite, SpawnedSprite};
+
+/// the text entity the frame time is written to while ShowDeltaTime is on
+#[derive(Clone, Debug)]
+struct DeltaTimeText(usize);
+
+/// the emitter kicking up dust behind the player while walking
+#[derive(Clone, Debug)]
+struct PlayerDust(usize);
+
 #[derive(Clone, Debug)]
 struct Count(usize);
 
@@ -698,12 +5064,26 @@ fn entry(table: &mut ecs::Table) {
         .unwrap()
         .to_vector(1f32);
     let player_index = table.read_resource::<PlayerIndex>().unwrap();
-    let player_sprite = table.read::<Sprite>(player_index.0).unwrap();
+    let player_sprite = table.read::<SpawnedSprite>(player_index.0).unwrap();
     let time = table.read_resource::<Time>().unwrap();
+    table
+        .read_resource::<GameClock>()
+        .unwrap()
+        .advance(time.delta_time);
 
-    if table.read_resource::<ShowDeltaTime>().unwrap().0 {
-        println!("{:?}", time.delta_time);
-    }
+    let delta_time_text = table.read_resource::<DeltaTimeText>().unwrap().0;
+    let viewport = *table.read_resource::<Viewport>().unwrap();
+    let text = table.read::<Text>(delta_time_text).unwrap();
+    text.string = if table.read_resource::<ShowDeltaTime>().unwrap().0 {
+        format!("{:.2} ms", time.delta_time * 1000.0)
+    } else {
+        String::new()
+    };
+    // pinned to the top left corner
+    text.position = (
+        -viewport.target_width / 2.0 + 2.0,
+        viewport.target_height / 2.0 - 2.0,
+    );
 
     for each in table.handle_event::<winit::event::KeyboardInput>().unwrap() {
         if let Some(code) = each.virtual_keycode {
@@ -752,13 +5132,78 @@ fn entry(table: &mut ecs::Table) {
 
                 winit::event::VirtualKeyCode::End => match each.state {
                     winit::event::ElementState::Pressed => {
-                        let mut sprite = player_sprite.clone();
-                        sprite.depth_base = 0.0;
-                        table.insert_new(sprite);
+                        let mut sprite = **player_sprite;
+                        sprite.layer = 0;
+                        spawn_sprite(table, sprite);
+                    }
+                    winit::event::ElementState::Released => {}
+                },
+
+                winit::event::VirtualKeyCode::Equals => match each.state {
+                    winit::event::ElementState::Pressed => {
+                        table.read_resource::<Camera>().unwrap().zoom_by(2.0);
+                    }
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::Minus => match each.state {
+                    winit::event::ElementState::Pressed => {
+                        table.read_resource::<Camera>().unwrap().zoom_by(0.5);
                     }
                     winit::event::ElementState::Released => {}
                 },
 
+                winit::event::VirtualKeyCode::F5 => match each.state {
+                    winit::event::ElementState::Pressed => table
+                        .read_resource::<PostEffects>()
+                        .unwrap()
+                        .toggle(PostEffectKind::ColorGrade),
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::F6 => match each.state {
+                    winit::event::ElementState::Pressed => table
+                        .read_resource::<PostEffects>()
+                        .unwrap()
+                        .toggle(PostEffectKind::Vignette),
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::F7 => match each.state {
+                    winit::event::ElementState::Pressed => table
+                        .read_resource::<PostEffects>()
+                        .unwrap()
+                        .toggle(PostEffectKind::Desaturate),
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::F8 => match each.state {
+                    winit::event::ElementState::Pressed => table
+                        .read_resource::<PostEffects>()
+                        .unwrap()
+                        .toggle(PostEffectKind::Fade),
+                    winit::event::ElementState::Released => {}
+                },
+
+                winit::event::VirtualKeyCode::F2 => match each.state {
+                    winit::event::ElementState::Pressed => {
+                        table.read_resource::<Weather>().unwrap().cycle();
+                    }
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::F3 => match each.state {
+                    winit::event::ElementState::Pressed => {
+                        let debug_draw = table.read_resource::<DebugDraw>().unwrap();
+                        debug_draw.enabled = !debug_draw.enabled;
+                    }
+                    winit::event::ElementState::Released => {}
+                },
+                winit::event::VirtualKeyCode::F4 => match each.state {
+                    winit::event::ElementState::Pressed => {
+                        let occlusion = table.read_resource::<Occlusion>().unwrap();
+                        occlusion.mode = match occlusion.mode {
+                            OcclusionMode::Silhouette => OcclusionMode::FadeOccluders,
+                            OcclusionMode::FadeOccluders => OcclusionMode::Silhouette,
+                        };
+                    }
+                    winit::event::ElementState::Released => {}
+                },
                 winit::event::VirtualKeyCode::F11 => match each.state {
                     winit::event::ElementState::Pressed => {
                         let status = table.read_resource::<ShowDeltaTime>().unwrap();
@@ -779,13 +5224,273 @@ fn entry(table: &mut ecs::Table) {
     // player_sprite.top_left_position_y += vector.1 as f32 * time.delta_time;
     player_sprite.top_left_position_x += vector.0 as f32;
     player_sprite.top_left_position_y += vector.1 as f32;
+    let player_dust = table.read_resource::<PlayerDust>().unwrap().0;
+    table.read::<Emitter>(player_dust).unwrap().rate = match vector {
+        (0.0, 0.0) => 0.0,
+        _ => 16.0,
+    };
+    // face the way we're walking, keep facing it when standing still
+    if vector.0 != 0.0 {
+        player_sprite.flip_x = (vector.0 < 0.0) as u32;
+    }
+    if vector != (0.0, 0.0) {
+        let highlight = table.read_resource::<Highlight>().unwrap();
+        highlight.facing = vector;
+        highlight.cursor = None;
+    }
+    update_highlight(table, player_sprite);
+
+    // the fade goes to black over a second once it's on and drops away at once when it's off
+    if let Some(fade) = table
+        .read_resource::<PostEffects>()
+        .unwrap()
+        .get_mut(PostEffectKind::Fade)
+    {
+        fade.amount = if fade.enabled {
+            (fade.amount + time.delta_time).min(1.0)
+        } else {
+            0.0
+        };
+    }
+
+    let fog = table.read_resource::<Weather>().unwrap().fog;
+    if let Some(effect) = table
+        .read_resource::<PostEffects>()
+        .unwrap()
+        .get_mut(PostEffectKind::Fog)
+    {
+        effect.amount = fog;
+    }
+
+    // keep the middle of the player's sprite in the centre of the screen
+    table.read_resource::<Camera>().unwrap().follow(
+        (
+            player_sprite.top_left_position_x + player_sprite.width / 2.0,
+            player_sprite.top_left_position_y - player_sprite.height / 2.0,
+        ),
+        time.delta_time,
+    );
+}
+
+/// packs src/res/sprites into the atlas and points the prefabs into it
+fn load_prefabs(renderer: &mut PixelRenderer) -> Result<Prefab, Box<dyn std::error::Error>> {
+    let dir = std::env::current_dir()?.join("src/res");
+    let atlas = pack_atlas(dir.join("sprites"), renderer.max_texture_size())?;
+    renderer.load_atlas(&atlas.image)?;
+    Ok(Prefab::load(
+        dir.join("prefabs.json"),
+        &atlas,
+        &renderer.layers,
+        &renderer.palettes,
+    )?)
+}
+
+/// the field the game starts on
+fn ground_tilemap(
+    prefab: &Prefab,
+    layers: &RenderLayers,
+) -> Result<Tilemap, Box<dyn std::error::Error>> {
+    let mut tilemap = Tilemap::new(32);
+    let grass = prefab.tile("bg_tile", &mut tilemap)?;
+    let flowers = prefab.tile("flower_tile", &mut tilemap)?;
+    let water = prefab.tile("water_tile", &mut tilemap)?;
+    let ground = layers
+        .get("ground")
+        .ok_or_else(|| PrefabError::InvalidLayer {
+            name: "bg_tile".to_string(),
+            layer: "ground".to_string(),
+        })?;
+    tilemap.fill(ground, (-8, -8), (7, 7), grass)?;
+    for position in [(2, 1), (-5, 3), (4, -4), (-2, -5), (5, 4)] {
+        tilemap.set_tile(ground, position, flowers)?;
+    }
+    tilemap.fill(ground, (-4, -2), (-3, -1), water)?;
+    Ok(tilemap)
+}
+
+fn player_lantern(player: Option<usize>) -> Light {
+    Light {
+        entity: player,
+        offset: (0.0, 8.0),
+        radius: 48.0,
+        color: [1.0, 0.85, 0.6],
+        intensity: 0.8,
+    }
+}
+
+fn player_dust(player: Option<usize>, prefab: &Prefab) -> Result<Emitter, PrefabError> {
+    Ok(Emitter {
+        entity: player,
+        offset: (0.0, 0.0),
+        spread: (4.0, 0.0),
+        rate: 0.0,
+        seed: 1,
+        lifetime: (0.4, 0.7),
+        velocity_min: (-8.0, 4.0),
+        velocity_max: (8.0, 12.0),
+        gravity: 16.0,
+        color_start: [0.85, 0.75, 0.6, 0.9],
+        color_end: [0.85, 0.75, 0.6, 0.0],
+        size_start: 1.0,
+        size_end: 1.5,
+        frames: prefab.particle_frames("dust")?,
+    })
+}
+
+/// dev mode, polls shader.wgsl and src/res/sprites and rebuilds them in place when they change
+struct HotReload {
+    shader_path: std::path::PathBuf,
+    sprites_dir: std::path::PathBuf,
+    shader_modified: Option<std::time::SystemTime>,
+    sprites_modified: Option<std::time::SystemTime>,
+    // what the loaded prefabs point at, a repack that moves anything needs a restart
+    regions: std::collections::HashMap<String, AtlasRegion>,
+    last_poll: std::time::Instant,
+}
+impl HotReload {
+    fn new(dir: &std::path::Path, prefab: &Prefab) -> Self {
+        let shader_path = dir.join("src/shader.wgsl");
+        let sprites_dir = dir.join("src/res/sprites");
+        Self {
+            shader_modified: Self::modified(&shader_path),
+            sprites_modified: Self::latest_modified(&sprites_dir),
+            shader_path,
+            sprites_dir,
+            regions: prefab.regions.clone(),
+            last_poll: std::time::Instant::now(),
+        }
+    }
+
+    fn modified(path: &std::path::Path) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// of the folder and everything in it, the folder's own time changes when a file is
+    /// added or removed
+    fn latest_modified(dir: &std::path::Path) -> Option<std::time::SystemTime> {
+        std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| Self::modified(&entry.ok()?.path()))
+            .chain(Self::modified(dir))
+            .max()
+    }
+
+    /// errors are printed rather than returned, a half saved file shouldn't end the session
+    fn poll(&mut self, renderer: &mut PixelRenderer) {
+        // a few times a second is plenty and keeps the stat calls out of most frames
+        if self.last_poll.elapsed() < std::time::Duration::from_millis(250) {
+            return;
+        }
+        self.last_poll = std::time::Instant::now();
+
+        let shader_modified = Self::modified(&self.shader_path);
+        if shader_modified != self.shader_modified {
+            self.shader_modified = shader_modified;
+            match std::fs::read_to_string(&self.shader_path) {
+                Ok(source) => match renderer.reload_shader(&source) {
+                    Ok(()) => println!("reloaded {}", self.shader_path.display()),
+                    Err(err) => eprintln!("{err}"),
+                },
+                Err(err) => eprintln!("could not read {}: {err}", self.shader_path.display()),
+            }
+        }
+
+        let sprites_modified = Self::latest_modified(&self.sprites_dir);
+        if sprites_modified != self.sprites_modified {
+            self.sprites_modified = sprites_modified;
+            match pack_atlas(&self.sprites_dir, renderer.max_texture_size()) {
+                // sprites already out in the world keep their texture coordinates, so only
+                // repainted art can be swapped in
+                Ok(atlas) if atlas.regions != self.regions => eprintln!(
+                    "sprites in {} were added, removed or resized, restart to repack them",
+                    self.sprites_dir.display()
+                ),
+                Ok(atlas) => match renderer.load_atlas(&atlas.image) {
+                    Ok(()) => println!("reloaded {}", self.sprites_dir.display()),
+                    Err(err) => eprintln!("{err}"),
+                },
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+    }
+}
+
+/// uses src/res/lut.png for colour grading when it's there
+fn load_color_grade(renderer: &mut PixelRenderer) -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::current_dir()?.join("src/res/lut.png");
+    if path.is_file() {
+        renderer.load_lut(&image::open(path)?.into_rgba8())?;
+    }
+    Ok(())
+}
+
+/// needs to run before load_prefabs so prefabs can name their palette
+fn load_palettes(renderer: &mut PixelRenderer) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::current_dir()?.join("src/res");
+    if dir.join("palettes.json").is_file() {
+        let names = serde_json::from_str(&std::fs::read_to_string(dir.join("palettes.json"))?)?;
+        renderer.load_palettes(
+            &image::open(dir.join("palettes.png"))?.into_rgba8(),
+            Palettes(names),
+        )?;
+    }
+    Ok(())
+}
+
+/// renders the opening scene once without a window and writes it to a png
+fn headless(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    headless_frame()?.save(path)?;
+    Ok(())
+}
+
+/// the opening scene at time 0, so the same build always draws the same frame
+fn headless_frame() -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+    let mut renderer = PixelRenderer::new_headless(800, 600, 144)?;
+    load_palettes(&mut renderer)?;
+    let prefab = load_prefabs(&mut renderer)?;
+    load_color_grade(&mut renderer)?;
+    renderer.set_post_effects(&PostEffects::new());
+    renderer.set_time(0.0);
+    renderer.load_tilemap(&mut ground_tilemap(&prefab, &renderer.layers)?);
+    let player = prefab.gen("char_main", (0.0, 0.0))?;
+    renderer.load_lights(
+        GameClock::new(6.0).ambient(),
+        &[player_lantern(None).to_instance(Some(&player))],
+    );
+    renderer.load_sprites(&[0], &[player]);
+    renderer.render()?;
+    Ok(renderer.read_frame())
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--headless") {
+        if let Err(err) = headless(
+            args.get(index + 1)
+                .map_or("frame.png", |path| path.as_str()),
+        ) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // set by the capture keys, taken right after the next frame is rendered
+    let mut screenshot_requested = false;
+    let mut recording: Option<Recording> = None;
+
     // init
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::Window::new(&event_loop).unwrap();
-    let mut renderer = PixelRenderer::new(&window, 144);
+    let mut renderer = match PixelRenderer::new(&window, 144) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
     let mut ecs = ecs::ECS::new(entry);
     // prep
     ecs.table.register_event::<winit::event::KeyboardInput>();
@@ -797,17 +5502,114 @@ fn main() {
         })
         .unwrap();
     ecs.table.add_resource(CloseStatus::Running).unwrap();
-    let prefab = Prefab::new();
+    if let Err(err) = load_palettes(&mut renderer) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+    let prefab = match load_prefabs(&mut renderer) {
+        Ok(prefab) => prefab,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = load_color_grade(&mut renderer) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+    let mut hot_reload = match args.iter().any(|arg| arg == "--dev") {
+        true => Some(HotReload::new(&std::env::current_dir().unwrap(), &prefab)),
+        false => None,
+    };
+    ecs.table.add_resource(PostEffects::new()).unwrap();
+    ecs.table.add_resource(DebugDraw::new()).unwrap();
+    ecs.table.add_resource(Occlusion::new()).unwrap();
+    // for looking up layer ids by name, changes are handed to the renderer every frame
+    ecs.table.add_resource(renderer.layers.clone()).unwrap();
+    // and palette rows, a sprite's palette can be swapped at any time
+    ecs.table.add_resource(renderer.palettes.clone()).unwrap();
     ecs.table.add_resource(ArrowKeyState::new()).unwrap();
-    let player_index = ecs
-        .table
-        .insert_new(prefab.gen("char_main", (0.0, 0.0), 1, 2, 0.1, true));
+    ecs.table.add_resource(SpriteEntities::new()).unwrap();
+    let player_index = spawn_sprite(&mut ecs.table, prefab.gen("char_main", (0.0, 0.0)).unwrap());
     ecs.table.add_resource(PlayerIndex(player_index)).unwrap();
+    // the player's sprite through two other palettes
+    for (name, position) in [("char_autumn", (-56.0, 24.0)), ("char_mint", (40.0, 32.0))] {
+        let villager = spawn_sprite(&mut ecs.table, prefab.gen(name, position).unwrap());
+        ecs.table.insert_new(Interactable { entity: villager });
+    }
     ecs.table.add_resource(ShowDeltaTime(false)).unwrap();
-    // ecs.table
-    //     .insert_new(prefab.gen("char_alt", (0.0, 0.0), 1, 1, 0.0, false));
-    // ecs.table
-    //     .insert_new(prefab.gen("bg_tile", (0.0, 0.0), 2, 1, 0.0, false));
+    ecs.table.add_resource(Camera::new()).unwrap();
+    ecs.table.add_resource(renderer.viewport).unwrap();
+    let tilemap = match ground_tilemap(&prefab, &renderer.layers) {
+        Ok(tilemap) => tilemap,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    ecs.table.add_resource(tilemap).unwrap();
+    let font = match BitmapFont::load(
+        std::env::current_dir().unwrap().join("src/res/font.json"),
+        &prefab,
+    ) {
+        Ok(font) => font,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    ecs.table.add_resource(GameClock::new(6.0)).unwrap();
+    let weather_frames = match WeatherFrames::new(&prefab) {
+        Ok(frames) => frames,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    ecs.table
+        .add_resource(Weather::new(renderer.weather_quality, weather_frames))
+        .unwrap();
+    for column in -3..3 {
+        let crop = spawn_sprite(
+            &mut ecs.table,
+            prefab
+                .gen("crop", (column as f32 * 10.0 + 16.0, -24.0))
+                .unwrap(),
+        );
+        ecs.table.insert_new(Interactable { entity: crop });
+    }
+    let tile_marker = spawn_sprite(
+        &mut ecs.table,
+        Sprite {
+            layer: renderer.layers.get("ground").unwrap_or(0),
+            opacity: 0.0,
+            ..Sprite::empty()
+        },
+    );
+    ecs.table.add_resource(Highlight::new(tile_marker)).unwrap();
+    ecs.table.insert_new(player_lantern(Some(player_index)));
+    let player_dust = match player_dust(Some(player_index), &prefab) {
+        Ok(emitter) => ecs.table.insert_new(emitter),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    ecs.table.add_resource(PlayerDust(player_dust)).unwrap();
+    let mut delta_time_text = Text::new("", (0.0, 0.0));
+    delta_time_text.screen_space = true;
+    let delta_time_text = ecs.table.insert_new(delta_time_text);
+    ecs.table
+        .add_resource(DeltaTimeText(delta_time_text))
+        .unwrap();
+    // scenery that never moves, uploaded once instead of going through the per tick diff
+    match prefab.gen("char_alt", (-88.0, -40.0)) {
+        Ok(prop) => renderer.load_static_sprites(&[prop]),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
     // loop
 
     event_loop.run(move |event, _, control_flow| {
@@ -817,11 +5619,15 @@ fn main() {
             CloseStatus::Closed => control_flow.set_exit(),
         }
         renderer.update_time();
+        if let Some(hot_reload) = &mut hot_reload {
+            hot_reload.poll(&mut renderer);
+        }
         *ecs.table.read_resource::<Time>().unwrap() = Time {
             start_time: renderer.start_time,
             utime: renderer.uniform_data.utime,
             delta_time: renderer.uniform_data.delta_time,
         };
+        *ecs.table.read_resource::<Viewport>().unwrap() = renderer.viewport;
         match event {
             winit::event::Event::WindowEvent { event, .. } => match event {
                 winit::event::WindowEvent::Resized(size) => {
@@ -829,6 +5635,13 @@ fn main() {
                     window.request_redraw();
                 }
                 winit::event::WindowEvent::CloseRequested => control_flow.set_exit(),
+                winit::event::WindowEvent::CursorMoved { position, .. } => {
+                    ecs.table.read_resource::<Highlight>().unwrap().cursor =
+                        Some((position.x as f32, position.y as f32));
+                }
+                winit::event::WindowEvent::CursorLeft { .. } => {
+                    ecs.table.read_resource::<Highlight>().unwrap().cursor = None;
+                }
                 winit::event::WindowEvent::KeyboardInput { input, .. } => {
                     if ecs
                         .table
@@ -850,10 +5663,33 @@ fn main() {
                             },
                             winit::event::VirtualKeyCode::PageUp => match input.state {
                                 winit::event::ElementState::Pressed => {
-                                    println!("{:?}", renderer.storage_buffer.size());
+                                    println!("{:?}", renderer.instance_buffer.size());
                                 }
                                 winit::event::ElementState::Released => {}
                             },
+                            winit::event::VirtualKeyCode::F10 => match input.state {
+                                winit::event::ElementState::Pressed => {
+                                    renderer.set_scale_mode(renderer.scale_mode.next());
+                                }
+                                winit::event::ElementState::Released => {}
+                            },
+                            winit::event::VirtualKeyCode::F12 => match input.state {
+                                winit::event::ElementState::Pressed => {
+                                    screenshot_requested = true;
+                                }
+                                winit::event::ElementState::Released => {}
+                            },
+                            // starts a recording, or stops it early and saves what's there
+                            winit::event::VirtualKeyCode::F9 => match input.state {
+                                winit::event::ElementState::Pressed => match recording.take() {
+                                    Some(finished) => match capture_path("recording", "gif") {
+                                        Ok(path) => finished.save(path),
+                                        Err(err) => eprintln!("{err}"),
+                                    },
+                                    None => recording = Some(Recording::new(300)),
+                                },
+                                winit::event::ElementState::Released => {}
+                            },
                             winit::event::VirtualKeyCode::PageDown => match input.state {
                                 winit::event::ElementState::Pressed => {
                                     println!("{:?}", renderer.sorted_sprites.len());
@@ -868,11 +5704,469 @@ fn main() {
             },
             winit::event::Event::MainEventsCleared => window.request_redraw(),
             winit::event::Event::RedrawRequested(_) => {
-                renderer.render();
+                if let Err(err) = renderer.render() {
+                    eprintln!("{err}");
+                    control_flow.set_exit();
+                }
+                if screenshot_requested {
+                    screenshot_requested = false;
+                    match capture_path("screenshot", "png") {
+                        Ok(path) => match renderer.read_scene().save(&path) {
+                            Ok(()) => println!("saved {}", path.display()),
+                            Err(err) => eprintln!("could not save {}: {err}", path.display()),
+                        },
+                        Err(err) => eprintln!("{err}"),
+                    }
+                }
+                if let Some(current) = &mut recording {
+                    if current.wants_frame() && current.push(renderer.read_scene()) {
+                        match capture_path("recording", "gif") {
+                            Ok(path) => recording.take().unwrap().save(path),
+                            Err(err) => eprintln!("{err}"),
+                        }
+                    }
+                }
             }
             _ => (),
         }
         ecs.tick();
-        renderer.load_sprites(&ecs.table.query_raw::<Sprite>().unwrap());
+        let layers = ecs.table.read_resource::<RenderLayers>().unwrap();
+        if *layers != renderer.layers {
+            // put back what the renderer kept so it isn't tried again every frame
+            if let Err(err) = renderer.set_layers(layers) {
+                eprintln!("{err}");
+                *layers = renderer.layers.clone();
+            }
+        }
+        renderer.update_camera(ecs.table.read_resource::<Camera>().unwrap());
+        renderer.load_tilemap(ecs.table.read_resource::<Tilemap>().unwrap());
+        let lights: Vec<LightInstance> = ecs
+            .table
+            .query_raw::<Light>()
+            .unwrap_or_default()
+            .iter()
+            .map(|light| {
+                let sprite = light
+                    .entity
+                    .and_then(|entity| ecs.table.read::<SpawnedSprite>(entity))
+                    .map(|sprite| **sprite);
+                light.to_instance(sprite.as_ref())
+            })
+            .collect();
+        let ambient = ecs.table.read_resource::<GameClock>().unwrap().ambient();
+        renderer.load_lights(ambient, &lights);
+        let weather = ecs.table.read_resource::<Weather>().unwrap();
+        renderer.set_wind(weather.wind);
+        let weather_emitters = weather.emitters(
+            ecs.table.read_resource::<Camera>().unwrap(),
+            ecs.table.read_resource::<Viewport>().unwrap(),
+        );
+        let mut particles = vec![];
+        for emitter in ecs.table.query_raw::<Emitter>().unwrap_or_default() {
+            let sprite = emitter
+                .entity
+                .and_then(|entity| ecs.table.read::<SpawnedSprite>(entity))
+                .map(|sprite| **sprite);
+            emitter.emit(
+                sprite.as_ref(),
+                renderer.uniform_data.utime - renderer.uniform_data.delta_time,
+                renderer.uniform_data.utime,
+                &mut particles,
+            );
+        }
+        for emitter in weather_emitters.iter() {
+            emitter.emit(
+                None,
+                renderer.uniform_data.utime - renderer.uniform_data.delta_time,
+                renderer.uniform_data.utime,
+                &mut particles,
+            );
+        }
+        renderer.spawn_particles(&particles);
+        renderer.set_post_effects(ecs.table.read_resource::<PostEffects>().unwrap());
+        // removed entities drop out of the list here, and so do ids that went to another
+        // sprite since
+        let sprite_entities = ecs.table.read_resource::<SpriteEntities>().unwrap();
+        sprite_entities.entities.retain(|(entity, serial)| {
+            ecs.table
+                .read::<SpawnedSprite>(*entity)
+                .is_some_and(|sprite| sprite.serial() == *serial)
+        });
+        let (entities, serials): (Vec<usize>, Vec<u64>) =
+            sprite_entities.entities.iter().copied().unzip();
+        let mut sprites: Vec<Sprite> = entities
+            .iter()
+            .map(|entity| **ecs.table.read::<SpawnedSprite>(*entity).unwrap())
+            .collect();
+        let mut glyphs = vec![];
+        for text in ecs.table.query_raw::<Text>().unwrap_or_default() {
+            glyphs.extend(font.layout(&text));
+        }
+        let debug_draw = ecs.table.read_resource::<DebugDraw>().unwrap();
+        if debug_draw.enabled {
+            for sprite in sprites.iter().chain(glyphs.iter()) {
+                debug_draw.sprite(sprite);
+            }
+            debug_draw.tile_grid(ecs.table.read_resource::<Tilemap>().unwrap());
+        }
+        renderer.load_debug_lines(&debug_draw.vertices);
+        debug_draw.vertices.clear();
+        ecs.table
+            .read_resource::<Occlusion>()
+            .unwrap()
+            .fade_occluders(&mut sprites, &renderer.layers);
+        renderer.load_sprites(&serials, &sprites);
+        renderer.load_text_sprites(&glyphs);
     });
 }
+
+// ------------------------------------------------------------------ //
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    /// an empty directory under the system temp dir, unique to this test run
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("farm_game_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_png(dir: &std::path::Path, file: &str, size: (u32, u32), color: [u8; 4]) {
+        image::RgbaImage::from_pixel(size.0, size.1, image::Rgba(color))
+            .save(dir.join(file))
+            .unwrap();
+    }
+
+    #[test]
+    fn pack_atlas_rows_tallest_first() {
+        let dir = temp_dir("pack");
+        write_png(&dir, "wide.png", (8, 4), [255, 0, 0, 255]);
+        write_png(&dir, "tall.png", (4, 8), [0, 0, 255, 255]);
+        write_png(&dir, "dot.strip.png", (2, 2), [0, 255, 0, 255]);
+        std::fs::write(dir.join("notes.txt"), "not a sprite").unwrap();
+
+        let atlas = pack_atlas(&dir, 64).unwrap();
+        assert_eq!(atlas.image.dimensions(), (16, 8));
+        assert_eq!(atlas.regions.len(), 3);
+        let expected = [
+            ("tall", (0, 0), (4, 8), [0, 0, 255, 255]),
+            ("wide", (4, 0), (8, 4), [255, 0, 0, 255]),
+            ("dot", (12, 0), (2, 2), [0, 255, 0, 255]),
+        ];
+        for (name, position, size, color) in expected {
+            let region = &atlas.regions[name];
+            assert_eq!(region.top_left_tex_pos, position, "{name}");
+            assert_eq!(region.size, size, "{name}");
+            let far_corner = (position.0 + size.0 - 1, position.1 + size.1 - 1);
+            assert_eq!(atlas.image.get_pixel(position.0, position.1).0, color);
+            assert_eq!(atlas.image.get_pixel(far_corner.0, far_corner.1).0, color);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pack_atlas_errors() {
+        let dir = temp_dir("pack_too_large");
+        write_png(&dir, "big.png", (12, 12), [255; 4]);
+        assert!(matches!(
+            pack_atlas(&dir, 8),
+            Err(AtlasError::TooLarge {
+                width: 16,
+                height: 12,
+                max: 8
+            })
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let dir = temp_dir("pack_duplicate");
+        write_png(&dir, "crop.png", (2, 2), [255; 4]);
+        write_png(&dir, "crop.strip.png", (4, 2), [255; 4]);
+        match pack_atlas(&dir, 64) {
+            Err(AtlasError::DuplicateName { name, .. }) => assert_eq!(name, "crop"),
+            other => panic!("expected a duplicate name, got {:?}", other.err()),
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn test_font() -> BitmapFont {
+        BitmapFont {
+            sprite: "font".to_string(),
+            top_left_tex_pos: (64, 64),
+            glyph_size: (4, 6),
+            columns: 16,
+            first_char: 32,
+            glyph_count: 95,
+        }
+    }
+
+    #[test]
+    fn font_wrap() {
+        let font = test_font();
+        assert_eq!(font.wrap("hello world", None), ["hello world"]);
+        assert_eq!(font.wrap("hello world", Some(24.0)), ["hello", "world"]);
+        assert_eq!(font.wrap("a b c", Some(12.0)), ["a b", "c"]);
+        assert_eq!(font.wrap("abcdefghij", Some(16.0)), ["abcd", "efgh", "ij"]);
+        assert_eq!(font.wrap("one\ntwo", None), ["one", "two"]);
+        assert_eq!(font.wrap("", Some(16.0)), [""]);
+    }
+
+    #[test]
+    fn font_layout() {
+        let font = test_font();
+        let sprites = font.layout(&Text::new("A b\né", (10.0, 20.0)));
+        // the space is skipped
+        assert_eq!(sprites.len(), 3);
+        let positions: Vec<_> = sprites
+            .iter()
+            .map(|sprite| (sprite.top_left_position_x, sprite.top_left_position_y))
+            .collect();
+        assert_eq!(positions, [(10.0, 20.0), (18.0, 20.0), (10.0, 14.0)]);
+        let tex_coords: Vec<_> = sprites
+            .iter()
+            .map(|sprite| (sprite.top_left_tex_coords_x, sprite.top_left_tex_coords_y))
+            .collect();
+        // 'A' is glyph 33, 'b' glyph 66 and 'é' isn't in the font so it's '?', glyph 31
+        assert_eq!(tex_coords, [(68.0, 76.0), (72.0, 88.0), (124.0, 70.0)]);
+        // every glyph sorts by the bottom of the block
+        assert_eq!(sprites[0].origin_offset_y, 12.0);
+        assert_eq!(sprites[2].origin_offset_y, 6.0);
+
+        let mut text = Text::new("ab\nabcd", (0.0, 0.0));
+        text.max_width = Some(24.0);
+        text.align = TextAlign::Center;
+        assert_eq!(font.layout(&text)[0].top_left_position_x, 8.0);
+        text.align = TextAlign::Right;
+        assert_eq!(font.layout(&text)[0].top_left_position_x, 16.0);
+        assert_eq!(font.layout(&text)[2].top_left_position_x, 8.0);
+    }
+
+    #[test]
+    fn camera_round_trip() {
+        // whole multiples of 256x144 leave bars on every side of this window
+        let viewport = Viewport::new(ScaleMode::Integer, (256, 144), (1300, 800));
+        assert_eq!((viewport.x, viewport.y), (10.0, 40.0));
+        assert_eq!((viewport.width, viewport.height), (1280.0, 720.0));
+
+        let mut camera = Camera::new();
+        camera.position = (10.0, -5.0);
+        camera.zoom = 2.0;
+        assert_eq!(
+            camera.world_to_screen((10.0, -5.0), &viewport),
+            (650.0, 400.0)
+        );
+        // up in the world is up on screen
+        let above = camera.world_to_screen((10.0, -4.0), &viewport);
+        assert_eq!(above, (650.0, 390.0));
+
+        for scale_mode in [ScaleMode::Integer, ScaleMode::Fit, ScaleMode::Stretch] {
+            let viewport = Viewport::new(scale_mode, (256, 144), (1300, 800));
+            for world in [(0.0, 0.0), (3.5, 7.25), (-120.0, 64.0)] {
+                let screen = camera.world_to_screen(world, &viewport);
+                let back = camera.screen_to_world(screen, &viewport);
+                assert!(
+                    (back.0 - world.0).abs() < 1e-3 && (back.1 - world.1).abs() < 1e-3,
+                    "{scale_mode:?} {world:?} came back as {back:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tilemap_split() {
+        assert_eq!(Tilemap::split((0, 0)), ((0, 0), 0));
+        assert_eq!(Tilemap::split((15, 15)), ((0, 0), 255));
+        assert_eq!(Tilemap::split((17, 2)), ((1, 0), 33));
+        assert_eq!(Tilemap::split((-1, -1)), ((-1, -1), 255));
+        assert_eq!(Tilemap::split((-16, 5)), ((-1, 0), 80));
+        assert_eq!(Tilemap::split((-17, -32)), ((-2, -2), 15));
+    }
+
+    #[test]
+    fn clock_ambient() {
+        let ambient = |hours| GameClock::new(hours).ambient();
+        let assert_color = |actual: [f32; 3], expected: [f32; 3]| {
+            assert!(
+                actual.iter().zip(expected).all(|(a, b)| close(*a, b)),
+                "{actual:?} != {expected:?}"
+            );
+        };
+        let night = [0.15, 0.18, 0.35];
+        assert_color(ambient(5.0), night);
+        assert_color(ambient(7.0), [1.0, 0.8, 0.7]);
+        assert_color(ambient(12.0), [1.0, 1.0, 1.0]);
+        assert_color(ambient(8.0), [1.0, 0.9, 0.85]);
+        assert_color(ambient(20.0), [0.575, 0.415, 0.425]);
+        // 21 to 5 wraps around midnight
+        assert_color(ambient(23.0), night);
+        assert_color(ambient(0.0), night);
+    }
+
+    fn test_emitter(rate: f32) -> Emitter {
+        Emitter {
+            entity: None,
+            offset: (100.0, 50.0),
+            spread: (4.0, 2.0),
+            rate,
+            seed: 7,
+            lifetime: (1.0, 2.0),
+            velocity_min: (-1.0, 0.0),
+            velocity_max: (1.0, 3.0),
+            gravity: 0.0,
+            color_start: [1.0; 4],
+            color_end: [1.0; 4],
+            size_start: 1.0,
+            size_end: 1.0,
+            frames: ParticleFrames {
+                top_left_tex_pos: (0, 0),
+                frame_size: (1, 1),
+                frame_count: 1,
+                layer: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn emitter_births() {
+        let emitter = test_emitter(10.0);
+        let mut particles = vec![];
+        emitter.emit(None, 0.0, 1.0, &mut particles);
+        assert_eq!(particles.len(), 10);
+        // born at 0.1, 0.2 .. 1.0 and aged to 1.0
+        for (n, particle) in particles.iter().enumerate() {
+            assert!(
+                close(particle.age, 0.9 - n as f32 * 0.1),
+                "{}",
+                particle.age
+            );
+            assert!((particle.position_x - 100.0).abs() <= 2.0);
+            assert!((particle.position_y - 50.0).abs() <= 1.0);
+            assert!((1.0..=2.0).contains(&particle.lifetime));
+            assert_eq!(particle.layer, 2);
+        }
+
+        // emitting in two steps gives the same particles as emitting once
+        let mut whole = vec![];
+        emitter.emit(None, 0.0, 1.5, &mut whole);
+        let mut split = vec![];
+        emitter.emit(None, 0.0, 1.0, &mut split);
+        emitter.emit(None, 1.0, 1.5, &mut split);
+        assert_eq!(whole.len(), 15);
+        assert_eq!(split.len(), 15);
+        for (a, b) in whole.iter().zip(split.iter()) {
+            assert_eq!((a.position_x, a.velocity_y), (b.position_x, b.velocity_y));
+        }
+
+        // follows the sprite's origin
+        let sprite = Sprite {
+            top_left_position_x: 20.0,
+            top_left_position_y: 30.0,
+            width: 16.0,
+            origin_offset_y: 16.0,
+            ..Sprite::empty()
+        };
+        let mut particles = vec![];
+        emitter.emit(Some(&sprite), 0.0, 0.1, &mut particles);
+        assert_eq!(particles.len(), 1);
+        assert!((particles[0].position_x - 128.0).abs() <= 2.0);
+        assert!((particles[0].position_y - 64.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn emitter_limits() {
+        let mut particles = vec![];
+        test_emitter(0.0).emit(None, 0.0, 10.0, &mut particles);
+        test_emitter(10.0).emit(None, 1.0, 1.0, &mut particles);
+        test_emitter(10.0).emit(None, 2.0, 1.0, &mut particles);
+        assert!(particles.is_empty());
+        // a long stall only fills the ring once, with the youngest ones
+        test_emitter(10.0).emit(None, 0.0, 10_000.0, &mut particles);
+        assert_eq!(particles.len(), PARTICLE_CAPACITY);
+        assert!(close(particles.last().unwrap().age, 0.0));
+    }
+
+    #[test]
+    fn depth_order() {
+        use std::cmp::Ordering::*;
+        let layers = RenderLayers::new();
+        let sprite = |layer: &str, y: f32, z_index: i32, order: u32| Sprite {
+            layer: layers.get(layer).unwrap(),
+            top_left_position_y: y,
+            z_index,
+            order,
+            ..Sprite::empty()
+        };
+        let order = |a: &Sprite, b: &Sprite| PixelRenderer::depth_order(a, b, &layers);
+
+        // layers further back come first whatever else they have
+        let ground = sprite("ground", -100.0, 5, 9);
+        let ui = sprite("ui", 100.0, -5, 0);
+        assert_eq!(order(&ground, &ui), Less);
+        assert_eq!(order(&ui, &ground), Greater);
+        // ids past the end are drawn on the last layer
+        let past_end = Sprite {
+            layer: 99,
+            order: 10,
+            ..ground
+        };
+        assert_eq!(order(&ground, &past_end), Less);
+
+        // higher up is further back
+        let high = sprite("characters", 10.0, 0, 1);
+        let low = sprite("characters", -10.0, 0, 0);
+        assert_eq!(order(&high, &low), Less);
+        let low_tall = Sprite {
+            top_left_position_y: 20.0,
+            origin_offset_y: 30.0,
+            ..low
+        };
+        assert_eq!(order(&high, &low_tall), Less);
+
+        let behind = sprite("ui", 0.0, -1, 1);
+        let in_front = sprite("ui", 0.0, 3, 0);
+        assert_eq!(order(&behind, &in_front), Less);
+
+        let first = sprite("ground", 50.0, 0, 0);
+        let second = sprite("ground", -50.0, 0, 1);
+        assert_eq!(order(&first, &second), Less);
+
+        // ties go to the load order and a sprite is equal only to itself
+        let tie = sprite("characters", 10.0, 0, 2);
+        assert_eq!(order(&high, &tie), Less);
+        assert_eq!(order(&high, &high), Equal);
+    }
+
+    #[test]
+    fn headless_golden() {
+        let frame = match headless_frame() {
+            Ok(frame) => frame,
+            Err(err) => match err.downcast_ref::<RendererError>() {
+                Some(RendererError::NoAdapter) => {
+                    eprintln!("skipping, {err}");
+                    return;
+                }
+                _ => panic!("{err}"),
+            },
+        };
+        let path = std::env::current_dir()
+            .unwrap()
+            .join("src/res/golden/headless.png");
+        let golden = image::open(&path).unwrap().into_rgba8();
+        assert_eq!(frame.dimensions(), golden.dimensions());
+        // adapters are allowed to round a little differently
+        let mismatched = frame
+            .pixels()
+            .zip(golden.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > 2))
+            .count();
+        if mismatched > 0 {
+            let _ = frame.save(std::env::temp_dir().join("farm_game_headless.png"));
+        }
+        assert_eq!(mismatched, 0, "pixels differing from {}", path.display());
+    }
+}
@@ -0,0 +1,53 @@
+// seconds since the particles were last stepped. uniform_data.delta_time only covers the
+// last window event, several of which can pass between two frames
+struct ParticleStep {
+    delta_time: f32,
+    _padding_0: u32,
+    _padding_1: u32,
+    _padding_2: u32,
+}
+
+struct Particle {
+    position_x: f32,
+    position_y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    age: f32,
+    lifetime: f32,
+    gravity: f32,
+    layer: u32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    size_start: f32,
+    size_end: f32,
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    frame_width: f32,
+    frame_height: f32,
+    frame_count: u32,
+    _padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> particle_step: ParticleStep;
+@group(0) @binding(1) var<storage, read_write> particle_array: array<Particle>;
+
+// one invocation per slot, dead particles are left alone until a new one is written over them
+@compute @workgroup_size(64)
+fn cs_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&particle_array) {
+        return;
+    }
+    var particle = particle_array[id.x];
+    if particle.age >= particle.lifetime {
+        return;
+    }
+    let delta_time = particle_step.delta_time;
+    particle.age += delta_time;
+    // one spawned partway through the step only moves for the part after its birth
+    let moving_time = clamp(particle.age, 0.0, delta_time);
+    // y goes up in the world, so gravity pulls it down
+    particle.velocity_y -= particle.gravity * moving_time;
+    particle.position_x += particle.velocity_x * moving_time;
+    particle.position_y += particle.velocity_y * moving_time;
+    particle_array[id.x] = particle;
+}
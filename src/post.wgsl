@@ -0,0 +1,135 @@
+struct PostData {
+    color_grade: f32,
+    vignette: f32,
+    fade: f32,
+    desaturate: f32,
+    fog: f32,
+    // 0 leaves the fog flat, for weak adapters
+    fog_octaves: u32,
+    // the camera plus how far the wind has carried the fog, world units
+    fog_origin_x: f32,
+    fog_origin_y: f32,
+    fog_zoom: f32,
+    _padding_0: u32,
+    _padding_1: u32,
+    _padding_2: u32,
+}
+
+// the previous step's output, the same size as the target so pixels map one to one
+@group(0) @binding(0) var source_texture: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> post_data: PostData;
+// a strip of blue slices side by side, red across each slice and green going down
+@group(0) @binding(2) var lut_texture: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_post(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn load_source(in: VertexOutput) -> vec4<f32> {
+    return textureLoad(source_texture, vec2<i32>(in.position.xy), 0);
+}
+
+// luts are authored against what's on screen, so they're indexed with srgb values
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn lut_texel(index: vec3<i32>, size: i32) -> vec3<f32> {
+    return textureLoad(lut_texture, vec2<i32>(index.z * size + index.x, index.y), 0).rgb;
+}
+
+@fragment
+fn fs_color_grade(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load_source(in);
+    let size = i32(textureDimensions(lut_texture).y);
+    let scaled = clamp(linear_to_srgb(color.rgb), vec3<f32>(0.0), vec3<f32>(1.0)) * f32(size - 1);
+    let base = vec3<i32>(floor(scaled));
+    let next = min(base + vec3<i32>(1), vec3<i32>(size - 1));
+    let t = fract(scaled);
+
+    // trilinear by hand, the lut is far too coarse to read nearest
+    let c00 = mix(lut_texel(vec3<i32>(base.x, base.y, base.z), size), lut_texel(vec3<i32>(next.x, base.y, base.z), size), t.x);
+    let c10 = mix(lut_texel(vec3<i32>(base.x, next.y, base.z), size), lut_texel(vec3<i32>(next.x, next.y, base.z), size), t.x);
+    let c01 = mix(lut_texel(vec3<i32>(base.x, base.y, next.z), size), lut_texel(vec3<i32>(next.x, base.y, next.z), size), t.x);
+    let c11 = mix(lut_texel(vec3<i32>(base.x, next.y, next.z), size), lut_texel(vec3<i32>(next.x, next.y, next.z), size), t.x);
+    let graded = mix(mix(c00, c10, t.y), mix(c01, c11, t.y), t.z);
+    return vec4<f32>(mix(color.rgb, graded, post_data.color_grade), color.a);
+}
+
+@fragment
+fn fs_vignette(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load_source(in);
+    // 0 in the centre, 1 in the corners
+    let distance = length(in.uv - vec2<f32>(0.5)) * 1.4142;
+    let darken = post_data.vignette * smoothstep(0.4, 1.0, distance);
+    return vec4<f32>(color.rgb * (1.0 - darken), color.a);
+}
+
+@fragment
+fn fs_fade(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load_source(in);
+    return vec4<f32>(color.rgb * (1.0 - post_data.fade), color.a);
+}
+
+@fragment
+fn fs_desaturate(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load_source(in);
+    let luma = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return vec4<f32>(mix(color.rgb, vec3<f32>(luma), post_data.desaturate), color.a);
+}
+
+fn hash(cell: vec2<i32>) -> f32 {
+    var x = (bitcast<u32>(cell.x) * 0x8da6b343u) ^ (bitcast<u32>(cell.y) * 0xd8163841u);
+    x = (x ^ (x >> 16u)) * 0x7feb352du;
+    x = (x ^ (x >> 15u)) * 0x846ca68bu;
+    x = x ^ (x >> 16u);
+    return f32(x) / 4294967295.0;
+}
+
+// random on whole numbers and smooth in between
+fn value_noise(point: vec2<f32>) -> f32 {
+    let cell = vec2<i32>(floor(point));
+    let t = fract(point);
+    let s = t * t * (3.0 - 2.0 * t);
+    let top = mix(hash(cell), hash(cell + vec2<i32>(1, 0)), s.x);
+    let bottom = mix(hash(cell + vec2<i32>(0, 1)), hash(cell + vec2<i32>(1, 1)), s.x);
+    return mix(top, bottom, s.y);
+}
+
+@fragment
+fn fs_fog(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load_source(in);
+    // back to world units so the fog stays where it is when the camera moves
+    let centre = vec2<f32>(textureDimensions(source_texture)) / 2.0;
+    let world = vec2<f32>(post_data.fog_origin_x, post_data.fog_origin_y) + (in.position.xy - centre) * vec2<f32>(1.0, -1.0) / post_data.fog_zoom;
+
+    var density = 0.6;
+    if post_data.fog_octaves > 0u {
+        var total = 0.0;
+        var weight = 0.0;
+        var amplitude = 1.0;
+        var frequency = 1.0 / 64.0;
+        for (var octave = 0u; octave < post_data.fog_octaves; octave++) {
+            total += value_noise(world * frequency) * amplitude;
+            weight += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        // pushed apart so there are clear patches and thick banks rather than an even haze
+        density = 0.15 + 0.85 * smoothstep(0.3, 0.75, total / weight);
+    }
+    let fog_color = vec3<f32>(0.78, 0.82, 0.86);
+    return vec4<f32>(mix(color.rgb, fog_color, post_data.fog * density * 0.8), color.a);
+}
@@ -5,17 +5,40 @@ struct Sprite {
     top_left_tex_coords_y: f32,
     width: f32,
     height: f32,
-    depth_base: f32,
+    layer: u32,
     origin_offset_y: f32,
     
     frame_num: u32,
     frame_interval: f32,
     looping: u32,
+    anim_index: u32,
+    order: u32,
+
+    tint_r: f32,
+    tint_g: f32,
+    tint_b: f32,
+    tint_a: f32,
+    screen_space: u32,
+    flip_x: u32,
+    flip_y: u32,
+    rotation: u32,
+    opacity: f32,
+    z_index: i32,
+    // packed rgba8, 0 for none
+    silhouette: u32,
+    // pixels the top leans over at full wind, 0 for none
+    sway: f32,
+    // row in palette_texture, 0 for none
+    palette: u32,
+    // packed rgba8, 0 for none
+    outline: u32,
 }
 
 struct Animation {
     counter: f32,
     current_frame_index: u32,
+    started: u32,
+    _padding: u32,
 }
 
 struct UniformData {
@@ -25,106 +48,458 @@ struct UniformData {
     window_width: f32,
     window_height: f32,
     utime: f32,
-    // these two are not used for right now
-    _dtime: f32,
-    _lasttime: f32,
+    delta_time: f32,
+    last_frame_time: f32,
+    sprite_count: u32,
+    camera_x: f32,
+    camera_y: f32,
+    zoom: f32,
+    target_width: f32,
+    target_height: f32,
+    wind: f32,
 }
 
 @group(0) @binding(1) var<uniform> uniform_data: UniformData;
-@group(0) @binding(2) var<storage, read_write> storage_array: array<Sprite>;
-@group(0) @binding(3) var<storage, read_write> anim_storage_array: array<Animation>;
+@group(0) @binding(2) var<storage, read> instance_array: array<Sprite>;
+@group(0) @binding(3) var<storage, read> anim_storage_array: array<Animation>;
+
+// see RenderLayers in main.rs, ids past the end are clamped to the last layer
+struct Layer {
+    depth_base: f32,
+    depth_range: f32,
+    tile_depth: f32,
+    sort_mode: u32,
+}
+
+@group(0) @binding(5) var<storage, read> layer_array: array<Layer>;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) tint: vec4<f32>,
+    // 0 leaves the sprite out of the lighting
+    @location(2) lit: f32,
+    @location(3) silhouette: vec4<f32>,
+    // TileMaterial for tiles, 0 for everything else
+    @location(4) @interpolate(flat) material: u32,
+    // the current frame in the atlas as x, y, width and height. water wraps inside it and
+    // outlined sprites never read past it
+    @location(5) @interpolate(flat) frame_rect: vec4<f32>,
+    // see remap, 0 for everything but sprites that name a palette
+    @location(6) @interpolate(flat) palette: u32,
+    // 0 alpha for none, see shade_outlined
+    @location(7) @interpolate(flat) outline: vec4<f32>,
 }
 
-fn gridify(pixel_val: f32) -> f32 {
-    return floor(pixel_val) / uniform_data.height_resolution;
+// the camera is snapped to whole pixels too so the scene never shifts by a fraction of one
+fn gridify(pixel_val: f32, camera_val: f32, zoom: f32) -> f32 {
+    return (floor(pixel_val) - floor(camera_val)) * zoom / uniform_data.height_resolution;
 }
 
-@vertex
-fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+// a pixel more on every side of an outlined sprite, the texture coordinates follow so the
+// extra ring reads just past the frame
+fn outline_corner(unit_corner: vec2<f32>, sprite: Sprite) -> vec2<f32> {
+    if unpack4x8unorm(sprite.outline).a <= 0.0 {
+        return unit_corner;
+    }
+    var size = vec2<f32>(sprite.width, sprite.height);
+    if sprite.rotation % 2u == 1u {
+        size = size.yx;
+    }
+    return (unit_corner * (size + 2.0) - 1.0) / size;
+}
+
+// every sprite is an instance of the same unit quad, unit_corner is (0, 0) at the top left
+// and (1, 1) at the bottom right
+fn sprite_vertex(unit_corner: vec2<f32>, sprite_index: u32) -> VertexOutput {
     var out: VertexOutput;
-    let index = vertex_index / 6u;
-    let sprite = storage_array[index];
-    let scale = uniform_data.window_height / uniform_data.window_width;
-    let origin_pos_y = (sprite.top_left_position_y - sprite.origin_offset_y) / uniform_data.height_resolution;
-
-    // updating the time
-    // todo, something is wrong, pls fix this
-    // var anim_x_offset: f32;
-    // var anim_data = anim_storage_array[index];
-    // if sprite.frame_num != 1u && sprite.frame_num != 0u {
-    //     anim_data.counter += uniform_data.delta_time;
-    //     if anim_data.counter >= sprite.frame_interval {
-    //         if anim_data.current_frame_index <= sprite.frame_num {
-    //             anim_data.current_frame_index += 1u;
-    //         } else {
-    //             if sprite.looping == 1u {
-    //                 anim_data.current_frame_index = 0u;
-    //             }
-    //         }
-    //     }
-    // }
-    // anim_x_offset = f32(anim_data.current_frame_index) * sprite.width;
-
-    // if anim_storage_array[0].counter == 0.0 {
-    //     anim_storage_array[0].counter = uniform_data.utime;
-    // } else {
-    //     if uniform_data.utime - anim_storage_array[0].counter > 0.5 {
-    //         if anim_storage_array[0].current_frame_index == 0u {
-    //             anim_storage_array[0].current_frame_index = 1u;
-    //         } else {
-    //             anim_storage_array[0].current_frame_index = 0u;
-    //         }
-    //         anim_storage_array[0].counter = 0.0;
-    //     }
-    // }
-    // var anim_x_offset = f32(anim_storage_array[0].current_frame_index) * 32.0;
-    var anim_x_offset = 0.0;
+    let sprite = instance_array[sprite_index];
+    let scale = uniform_data.target_height / uniform_data.target_width;
 
+    // screen space sprites ignore the camera, (0, 0) is the centre of the screen
+    var camera = vec2<f32>(uniform_data.camera_x, uniform_data.camera_y);
+    var zoom = uniform_data.zoom;
+    if sprite.screen_space != 0u {
+        camera = vec2<f32>(0.0, 0.0);
+        zoom = 1.0;
+    }
+    let origin_pos_y = gridify(sprite.top_left_position_y - sprite.origin_offset_y, camera.y, zoom);
 
-    // even tho we only have four layers we only allow 0.2 range of depth change
-    let delta_depth = 0.2 * (origin_pos_y + 1.0) / 2.0;
-    switch vertex_index % 6u {
-        case 0u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x), gridify(sprite.top_left_position_y), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + anim_x_offset, sprite.top_left_tex_coords_y);
+    // the frame itself is advanced by cs_animate in animation.wgsl
+    var anim_x_offset = 0.0;
+    if sprite.frame_num > 1u {
+        let anim_data = anim_storage_array[sprite.anim_index];
+        anim_x_offset = f32(min(anim_data.current_frame_index, sprite.frame_num - 1u)) * sprite.width;
+    }
+
+    // 0 at the front of the layer's band and 1 at the back, this has to agree with
+    // depth_order in main.rs. sprites outside the view are clamped so they can't leave the band
+    let layer = layer_array[min(sprite.layer, arrayLength(&layer_array) - 1u)];
+    var sort_key = 0.0;
+    switch layer.sort_mode {
+        // SortMode::YSort
+        case 0u: {
+            sort_key = (clamp(origin_pos_y, -1.0, 1.0) + 1.0) / 2.0;
         }
-        case 1u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x), gridify(sprite.top_left_position_y - sprite.height), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + anim_x_offset, sprite.top_left_tex_coords_y + sprite.height);
+        // SortMode::ZIndex
+        case 1u: {
+            sort_key = 0.5 - clamp(f32(sprite.z_index), -1024.0, 1024.0) / 2048.0;
         }
-        case 2u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x + sprite.width), gridify(sprite.top_left_position_y), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + sprite.width + anim_x_offset, sprite.top_left_tex_coords_y);
+        // SortMode::Insertion
+        default: {
+            sort_key = 1.0 - f32(sprite.order + 1u) / f32(uniform_data.sprite_count + 1u);
         }
-        case 3u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x), gridify(sprite.top_left_position_y - sprite.height), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + anim_x_offset, sprite.top_left_tex_coords_y + sprite.height);
+    }
+
+    // quarter turns swap the sides of the quad on screen, it stays anchored at the top left
+    var size = vec2<f32>(sprite.width, sprite.height);
+    if sprite.rotation % 2u == 1u {
+        size = size.yx;
+    }
+    let corner = outline_corner(unit_corner, sprite);
+    // the top edge leans with the wind and the bottom one stays put. neighbours gust out of
+    // step with each other since the phase follows the position
+    var lean = 0.0;
+    if sprite.sway != 0.0 {
+        let gust = 0.7 + 0.3 * sin(uniform_data.utime * 2.0 + sprite.top_left_position_x * 0.35);
+        lean = sprite.sway * uniform_data.wind * gust * (1.0 - corner.y);
+    }
+    let position_x = sprite.top_left_position_x + corner.x * size.x + lean;
+    let position_y = sprite.top_left_position_y - corner.y * size.y;
+
+    // flips mirror the image on screen, then the corner is turned back into the atlas
+    var flipped = corner;
+    if sprite.flip_x != 0u {
+        flipped.x = 1.0 - flipped.x;
+    }
+    if sprite.flip_y != 0u {
+        flipped.y = 1.0 - flipped.y;
+    }
+    var tex_corner = flipped;
+    switch sprite.rotation % 4u {
+        case 1u: {
+            tex_corner = vec2<f32>(flipped.y, 1.0 - flipped.x);
         }
-        case 4u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x + sprite.width), gridify(sprite.top_left_position_y - sprite.height), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + sprite.width + anim_x_offset, sprite.top_left_tex_coords_y + sprite.height);
+        case 2u: {
+            tex_corner = vec2<f32>(1.0 - flipped.x, 1.0 - flipped.y);
         }
-        case 5u:{
-            out.position = vec4<f32>(scale * gridify(sprite.top_left_position_x + sprite.width), gridify(sprite.top_left_position_y), sprite.depth_base + delta_depth, 1.0);
-            out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + sprite.width + anim_x_offset, sprite.top_left_tex_coords_y);
+        case 3u: {
+            tex_corner = vec2<f32>(1.0 - flipped.y, flipped.x);
         }
-        default:{
-            out.position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
-            out.tex_coords = vec2<f32>(0.0, 0.0);
+        default: {}
+    }
+    out.position = vec4<f32>(scale * gridify(position_x, camera.x, zoom), gridify(position_y, camera.y, zoom), layer.depth_base + layer.depth_range * sort_key, 1.0);
+    out.tex_coords = vec2<f32>(sprite.top_left_tex_coords_x + tex_corner.x * sprite.width + anim_x_offset, sprite.top_left_tex_coords_y + tex_corner.y * sprite.height);
+    out.tint = vec4<f32>(sprite.tint_r, sprite.tint_g, sprite.tint_b, sprite.tint_a * sprite.opacity);
+    out.lit = f32(sprite.screen_space == 0u);
+    out.silhouette = unpack4x8unorm(sprite.silhouette);
+    out.palette = sprite.palette;
+    out.frame_rect = vec4<f32>(sprite.top_left_tex_coords_x + anim_x_offset, sprite.top_left_tex_coords_y, sprite.width, sprite.height);
+    out.outline = unpack4x8unorm(sprite.outline);
+    return out;
+}
+
+// sprite_index comes from the draw order rather than instance_index, which doesn't count
+// from the first instance of a draw on every backend
+@vertex
+fn vs_main(@location(0) corner: vec2<f32>, @location(1) sprite_index: u32) -> VertexOutput {
+    return sprite_vertex(corner, sprite_index);
+}
+
+// sprites without a silhouette are pushed past the far plane so they are clipped away
+@vertex
+fn vs_silhouette(@location(0) corner: vec2<f32>, @location(1) sprite_index: u32) -> VertexOutput {
+    var out = sprite_vertex(corner, sprite_index);
+    if instance_array[sprite_index].silhouette == 0u {
+        out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+    }
+    return out;
+}
+
+// ground tiles, see Tilemap in main.rs
+const CHUNK_SIZE: u32 = 16u;
+
+struct TileInstance {
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    visible: u32,
+    material: u32,
+    // every tile with the same id shows the same frame, they all run off utime
+    frame_count: u32,
+    frame_interval: f32,
+    _padding_0: u32,
+    _padding_1: u32,
+}
+
+struct ChunkData {
+    origin_x: f32,
+    origin_y: f32,
+    depth: f32,
+    tile_size: f32,
+}
+
+@group(1) @binding(0) var<uniform> chunk_data: ChunkData;
+@group(1) @binding(1) var<storage, read> tile_array: array<TileInstance>;
+
+// one instance per cell of the chunk, rows go up from the bottom left corner
+@vertex
+fn vs_tile(@location(0) corner: vec2<f32>, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tile = tile_array[instance_index];
+    // empty cells get pushed past the far plane so they are clipped away
+    if tile.visible == 0u {
+        out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+    let scale = uniform_data.target_height / uniform_data.target_width;
+    let column = f32(instance_index % CHUNK_SIZE);
+    let row = f32(instance_index / CHUNK_SIZE);
+    let position_x = chunk_data.origin_x + (column + corner.x) * chunk_data.tile_size;
+    let position_y = chunk_data.origin_y + (row + 1.0 - corner.y) * chunk_data.tile_size;
+    var frame = 0u;
+    if tile.frame_count > 1u && tile.frame_interval > 0.0 {
+        frame = u32(uniform_data.utime / tile.frame_interval) % tile.frame_count;
+    }
+    let frame_x = tile.top_left_tex_coords_x + f32(frame) * chunk_data.tile_size;
+    out.position = vec4<f32>(scale * gridify(position_x, uniform_data.camera_x, uniform_data.zoom), gridify(position_y, uniform_data.camera_y, uniform_data.zoom), chunk_data.depth, 1.0);
+    out.tex_coords = vec2<f32>(frame_x + corner.x * chunk_data.tile_size, tile.top_left_tex_coords_y + corner.y * chunk_data.tile_size);
+    out.tint = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    out.lit = 1.0;
+    out.silhouette = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    out.material = tile.material;
+    out.frame_rect = vec4<f32>(frame_x, tile.top_left_tex_coords_y, chunk_data.tile_size, chunk_data.tile_size);
+    return out;
+}
+
+// sprites mirrored about the row their origin is on, only ever shows on water through the
+// stencil fs_water_mask leaves behind
+@vertex
+fn vs_reflection(@location(0) corner: vec2<f32>, @location(1) sprite_index: u32) -> VertexOutput {
+    var out = sprite_vertex(corner, sprite_index);
+    let sprite = instance_array[sprite_index];
+    if sprite.screen_space != 0u {
+        out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+    var height = sprite.height;
+    if sprite.rotation % 2u == 1u {
+        height = sprite.width;
+    }
+    let origin_y = sprite.top_left_position_y - sprite.origin_offset_y;
+    let position_y = sprite.top_left_position_y - outline_corner(corner, sprite).y * height;
+    out.position.y = gridify(2.0 * origin_y - position_y, uniform_data.camera_y, uniform_data.zoom);
+    out.tint = vec4<f32>(out.tint.rgb * vec3<f32>(0.7, 0.8, 1.0), out.tint.a * 0.35);
+    return out;
+}
+
+// particles, simulated by cs_particles in particle.wgsl
+struct Particle {
+    position_x: f32,
+    position_y: f32,
+    velocity_x: f32,
+    velocity_y: f32,
+    age: f32,
+    lifetime: f32,
+    gravity: f32,
+    layer: u32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    size_start: f32,
+    size_end: f32,
+    top_left_tex_coords_x: f32,
+    top_left_tex_coords_y: f32,
+    frame_width: f32,
+    frame_height: f32,
+    frame_count: u32,
+    _padding: u32,
+}
+
+@group(1) @binding(0) var<storage, read> particle_array: array<Particle>;
+
+// the same as PARTICLE_CAPACITY in main.rs
+const PARTICLE_CAPACITY: u32 = 4096u;
+
+// one instance per slot, centred on the particle's position and sorted like a sprite whose
+// origin is that position. particle_index is the slot plus PARTICLE_CAPACITY times one past
+// the only layer to draw, or 0 for all of them
+@vertex
+fn vs_particle(@location(0) corner: vec2<f32>, @location(1) particle_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let particle = particle_array[particle_index % PARTICLE_CAPACITY];
+    let layer_id = min(particle.layer, arrayLength(&layer_array) - 1u);
+    let only_layer = particle_index / PARTICLE_CAPACITY;
+    // dead, not born yet or on another layer, pushed past the far plane so they are clipped away
+    if particle.age < 0.0 || particle.age >= particle.lifetime || (only_layer != 0u && only_layer != layer_id + 1u) {
+        out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+    let life = particle.age / particle.lifetime;
+    let scale = uniform_data.target_height / uniform_data.target_width;
+    let size = mix(particle.size_start, particle.size_end, life);
+    let width = round(particle.frame_width * size);
+    let height = round(particle.frame_height * size);
+    let position_x = particle.position_x - floor(width / 2.0) + corner.x * width;
+    let position_y = particle.position_y + floor(height / 2.0) - corner.y * height;
+
+    let layer = layer_array[layer_id];
+    var sort_key = 0.0;
+    switch layer.sort_mode {
+        // SortMode::YSort
+        case 0u: {
+            let origin_pos_y = gridify(particle.position_y, uniform_data.camera_y, uniform_data.zoom);
+            sort_key = (clamp(origin_pos_y, -1.0, 1.0) + 1.0) / 2.0;
+        }
+        // SortMode::ZIndex, the same as z_index 0
+        case 1u: {
+            sort_key = 0.5;
         }
+        // SortMode::Insertion, they come after every sprite
+        default: {}
     }
+
+    let frame = min(u32(life * f32(particle.frame_count)), particle.frame_count - 1u);
+    out.position = vec4<f32>(scale * gridify(position_x, uniform_data.camera_x, uniform_data.zoom), gridify(position_y, uniform_data.camera_y, uniform_data.zoom), layer.depth_base + layer.depth_range * sort_key, 1.0);
+    out.tex_coords = vec2<f32>(particle.top_left_tex_coords_x + (f32(frame) + corner.x) * particle.frame_width, particle.top_left_tex_coords_y + corner.y * particle.frame_height);
+    out.tint = mix(particle.color_start, particle.color_end, life);
+    out.lit = 1.0;
+    out.silhouette = vec4<f32>(0.0, 0.0, 0.0, 0.0);
     return out;
 }
 
     @group(0) @binding(0) var my_texture: texture_2d<f32>;
+// ambient plus point lights at scene resolution, drawn by light.wgsl before this pass
+@group(0) @binding(4) var light_map: texture_2d<f32>;
+// the first row lists the colours that get swapped, every row below is a palette holding
+// the replacement for each of them in the same column
+@group(0) @binding(6) var palette_texture: texture_2d<f32>;
 
-    @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var result = textureLoad(my_texture, vec2<i32>(in.tex_coords), 0);
+// TileMaterial::Water, the frame scrolls and wobbles in world space so neighbouring tiles
+// line up. it wraps inside the frame and never reads the atlas around it
+fn water_coords(in: VertexOutput) -> vec2<f32> {
+    let t = uniform_data.utime;
+    let centre = vec2<f32>(uniform_data.target_width, uniform_data.target_height) / 2.0;
+    let world = vec2<f32>(uniform_data.camera_x, uniform_data.camera_y) + (in.position.xy - centre) * vec2<f32>(1.0, -1.0) / uniform_data.zoom;
+    let wobble = vec2<f32>(sin(world.y * 0.5 + t * 2.0), sin(world.x * 0.35 + t * 1.3)) * 0.75;
+    let local = world * vec2<f32>(1.0, -1.0) + wobble + vec2<f32>(t * 3.0, t);
+    let size = in.frame_rect.zw;
+    return in.frame_rect.xy + local - size * floor(local / size);
+}
+
+// colours that aren't in the source row are left alone, so only the parts of a sprite drawn
+// with those exact colours change
+fn remap(color: vec4<f32>, palette: u32) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(palette_texture));
+    let row = min(i32(palette), size.y - 1);
+    for (var column = 0; column < size.x; column++) {
+        let source = textureLoad(palette_texture, vec2<i32>(column, 0), 0);
+        // both come out of the same srgb decode, only neighbouring dark shades are this close
+        if source.a > 0.0 && all(abs(source.rgb - color.rgb) < vec3<f32>(0.0001)) {
+            return vec4<f32>(textureLoad(palette_texture, vec2<i32>(column, row), 0).rgb, color.a);
+        }
+    }
+    return color;
+}
+
+// outlined quads reach a pixel past their frame, whatever is there in the atlas belongs to
+// some other sprite and counts as empty
+fn frame_texel(texel: vec2<i32>, frame_rect: vec4<f32>) -> vec4<f32> {
+    let start = vec2<i32>(frame_rect.xy);
+    let end = start + vec2<i32>(frame_rect.zw);
+    if any(texel < start) || any(texel >= end) {
+        return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    }
+    return textureLoad(my_texture, texel, 0);
+}
+
+// the atlas colour tinted and lit, shared by every sprite pass
+fn shade(in: VertexOutput) -> vec4<f32> {
+    var tex_coords = in.tex_coords;
+    if in.material == 1u {
+        tex_coords = water_coords(in);
+    }
+    // the tint multiplies, white glyphs in the font take on the text colour this way
+    var color = textureLoad(my_texture, vec2<i32>(tex_coords), 0);
+    if in.outline.a > 0.0 {
+        color = frame_texel(vec2<i32>(floor(tex_coords)), in.frame_rect);
+    }
+    if in.palette != 0u {
+        color = remap(color, in.palette);
+    }
+    let result = color * in.tint;
+    let light = min(textureLoad(light_map, vec2<i32>(in.position.xy), 0).rgb, vec3<f32>(1.0, 1.0, 1.0));
     // result.y = abs(sin(uniform_data.delta_time * 10000.0));
+    return vec4<f32>(result.rgb * mix(vec3<f32>(1.0, 1.0, 1.0), light, in.lit), result.a);
+}
+
+// empty texels touching a fully opaque one take the outline colour, unlit so it stands out
+// at night too. it goes by the atlas alpha, a sprite faded out completely keeps its outline
+fn shade_outlined(in: VertexOutput) -> vec4<f32> {
+    let result = shade(in);
+    if in.outline.a <= 0.0 {
+        return result;
+    }
+    let texel = vec2<i32>(floor(in.tex_coords));
+    if frame_texel(texel, in.frame_rect).a > 0.0 {
+        return result;
+    }
+    // diagonals too, so square corners come out closed
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            if frame_texel(texel + vec2<i32>(x, y), in.frame_rect).a >= 1.0 {
+                return in.outline;
+            }
+        }
+    }
+    return result;
+}
+
+// only fully opaque texels, these write depth so draw order doesn't matter for them
+@fragment
+fn fs_opaque(in: VertexOutput) -> @location(0) vec4<f32> {
+    let result = shade_outlined(in);
+    if result.a < 1.0 {
+        discard;
+    }
+    return result;
+}
+
+// everything fs_opaque threw away that still shows, blended back to front without
+// writing depth
+@fragment
+fn fs_translucent(in: VertexOutput) -> @location(0) vec4<f32> {
+    let result = shade_outlined(in);
+    if result.a >= 1.0 || result.a <= 0.0 {
+        discard;
+    }
+    return result;
+}
+
+// drawn last with the depth test flipped, so only the parts of a sprite that something
+// else covers come through. the stencil keeps overlapping silhouettes from stacking up
+@fragment
+fn fs_silhouette(in: VertexOutput) -> @location(0) vec4<f32> {
+    if shade(in).a < 1.0 {
+        discard;
+    }
+    return in.silhouette;
+}
+
+// marks the water that ended up on top after the opaque tiles, nothing is written to the
+// colour target
+@fragment
+fn fs_water_mask(in: VertexOutput) -> @location(0) vec4<f32> {
+    if in.material != 1u {
+        discard;
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
+
+// a faint copy with every few rows skipped, the gaps crawl down to look like ripples
+@fragment
+fn fs_reflection(in: VertexOutput) -> @location(0) vec4<f32> {
+    let result = shade(in);
+    if result.a <= 0.0 || (u32(in.position.y) + u32(uniform_data.utime * 6.0)) % 3u == 0u {
+        discard;
+    }
     return result;
 }